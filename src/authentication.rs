@@ -84,6 +84,35 @@ impl Mechanism {
         }
     }
 
+    /// Extracts the credentials from an initial response sent by the client
+    ///
+    /// This is the inverse of `response` without challenge. Mechanisms without initial
+    /// response give empty credentials.
+    pub fn credentials(self, response: Option<&str>) -> Result<Credentials, Error> {
+        let invalid = Error::ResponseParsing("Invalid initial response");
+        match (self, response) {
+            (Mechanism::Plain, Some(response)) => {
+                let mut parts = response.splitn(3, '\u{0}');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(""), Some(username), Some(password)) => {
+                        Ok(Credentials::new(username.to_string(), password.to_string()))
+                    }
+                    _ => Err(invalid),
+                }
+            }
+            (Mechanism::Xoauth2, Some(response)) => {
+                let (username, token) = response
+                    .strip_prefix("user=")
+                    .and_then(|response| response.strip_suffix("\x01\x01"))
+                    .and_then(|response| response.split_once("\x01auth=Bearer "))
+                    .ok_or(invalid)?;
+                Ok(Credentials::new(username.to_string(), token.to_string()))
+            }
+            (Mechanism::Login, None) => Ok(Credentials::new(String::new(), String::new())),
+            _ => Err(invalid),
+        }
+    }
+
     /// Returns the string to send to the server, using the provided username, password and
     /// challenge in some cases
     pub fn response(
@@ -103,11 +132,11 @@ impl Mechanism {
                 let decoded_challenge =
                     challenge.ok_or(Error::Client("This mechanism does expect a challenge"))?;
 
-                if vec!["User Name", "Username:", "Username"].contains(&decoded_challenge) {
+                if ["User Name", "Username:", "Username"].contains(&decoded_challenge) {
                     return Ok(credentials.authentication_identity.to_string());
                 }
 
-                if vec!["Password", "Password:"].contains(&decoded_challenge) {
+                if ["Password", "Password:"].contains(&decoded_challenge) {
                     return Ok(credentials.secret.to_string());
                 }
 
@@ -139,6 +168,13 @@ mod test {
             "\u{0}username\u{0}password"
         );
         assert!(mechanism.response(&credentials, Some("test")).is_err());
+        assert_eq!(
+            mechanism
+                .credentials(Some("\u{0}username\u{0}password"))
+                .unwrap(),
+            credentials
+        );
+        assert!(mechanism.credentials(Some("username")).is_err());
     }
 
     #[test]
@@ -172,5 +208,13 @@ mod test {
             "user=username\x01auth=Bearer vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg==\x01\x01"
         );
         assert!(mechanism.response(&credentials, Some("test")).is_err());
+        assert_eq!(
+            mechanism
+                .credentials(Some(
+                    "user=username\x01auth=Bearer vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg==\x01\x01"
+                ))
+                .unwrap(),
+            credentials
+        );
     }
 }
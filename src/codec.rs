@@ -3,12 +3,65 @@ use async_std::io::{Write, WriteExt};
 #[cfg(feature = "runtime-tokio")]
 use tokio::io::{AsyncWrite as Write, AsyncWriteExt};
 
-use futures::io;
+use log::warn;
+
+use crate::error::Error;
+
+/// Handling of bare CR and bare LF characters in the message
+///
+/// SMTP requires lines to be terminated by CRLF,
+/// see [RFC 5321, section 2.3.8](https://tools.ietf.org/html/rfc5321#section-2.3.8).
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum LineEndings {
+    /// Replace bare CR and bare LF by CRLF
+    #[default]
+    Normalize,
+    /// Fail with `Error::BareLineEnding` on bare CR or bare LF
+    ///
+    /// See `SmtpClient::reject_long_lines` for when the message is checked.
+    Reject,
+    /// Send bare CR and bare LF as-is
+    PassThrough,
+}
+
+/// Maximum length of a line, without the CRLF
+///
+/// [RFC 5321, section 4.5.3.1.6](https://tools.ietf.org/html/rfc5321#section-4.5.3.1.6)
+pub const MAX_LINE_LENGTH: usize = 998;
 
 /// The codec used for transparency
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct ClientCodec {
-    escape_count: u8,
+    line_endings: LineEndings,
+    /// Stuff dots after bare CR and LF which are passed through
+    smuggling_protection: bool,
+    /// Fail on lines longer than this
+    max_line_length: Option<usize>,
+    /// The last byte was a CR
+    cr: bool,
+    /// Nothing was written on the current line yet
+    line_start: bool,
+    /// The current line was started by a bare CR or LF
+    bare_line_start: bool,
+    /// Current line number, starting at 1
+    line: usize,
+    /// Length of the current line, without stuffed dot
+    line_length: usize,
+}
+
+impl Default for ClientCodec {
+    fn default() -> Self {
+        ClientCodec {
+            line_endings: LineEndings::default(),
+            smuggling_protection: true,
+            max_line_length: None,
+            cr: false,
+            line_start: true,
+            bare_line_start: false,
+            line: 1,
+            line_length: 0,
+        }
+    }
 }
 
 impl ClientCodec {
@@ -16,62 +69,175 @@ impl ClientCodec {
     pub fn new() -> Self {
         ClientCodec::default()
     }
+
+    /// Set the handling of bare CR and bare LF
+    pub fn line_endings(self, line_endings: LineEndings) -> Self {
+        Self {
+            line_endings,
+            ..self
+        }
+    }
+
+    /// Enable protection against SMTP smuggling
+    ///
+    /// Some servers also end the message on a dot surrounded by bare CR or LF, like `\n.\n`.
+    /// With `LineEndings::PassThrough`, a dot following a bare CR or LF is stuffed,
+    /// so the servers can not be tricked into ending the message early.
+    /// Other modes never send bare CR or LF.
+    pub fn smuggling_protection(self, enabled: bool) -> Self {
+        Self {
+            smuggling_protection: enabled,
+            ..self
+        }
+    }
+
+    /// Fail with `Error::LineTooLong` on lines longer than `max_line_length`
+    ///
+    /// The length does not include the CRLF and stuffed dots.
+    pub fn max_line_length(self, max_line_length: Option<usize>) -> Self {
+        Self {
+            max_line_length,
+            ..self
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.line_start = true;
+        self.bare_line_start = false;
+        self.line += 1;
+        self.line_length = 0;
+    }
+
+    /// Counts a byte of the current line.
+    fn add_to_line(&mut self) -> Result<(), Error> {
+        self.line_length += 1;
+        match self.max_line_length {
+            Some(max) if self.line_length > max => Err(Error::LineTooLong(self.line)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Handles a bare CR or LF which is passed through.
+    ///
+    /// It is counted as part of the line.
+    fn bare_line_ending(&mut self) -> Result<(), Error> {
+        self.line_start = self.smuggling_protection;
+        self.bare_line_start = self.smuggling_protection;
+        self.add_to_line()
+    }
 }
 
 impl ClientCodec {
-    /// Adds transparency
-    /// TODO: replace CR and LF by CRLF
-    #[allow(clippy::bool_to_int_with_if)]
-    pub async fn encode<W: Write + Unpin>(&mut self, frame: &[u8], mut buf: W) -> io::Result<()> {
-        match frame.len() {
-            0 => {
-                match self.escape_count {
-                    0 => buf.write_all(b"\r\n.\r\n").await?,
-                    1 => buf.write_all(b"\n.\r\n").await?,
-                    2 => buf.write_all(b".\r\n").await?,
-                    _ => unreachable!(),
+    /// Adds transparency and fixes line endings.
+    ///
+    /// An empty frame ends the message.
+    pub async fn encode<W: Write + Unpin>(
+        &mut self,
+        frame: &[u8],
+        mut buf: W,
+    ) -> Result<(), Error> {
+        if frame.is_empty() {
+            if self.cr {
+                if self.line_endings == LineEndings::Reject {
+                    return Err(Error::BareLineEnding(self.line));
                 }
-                self.escape_count = 0;
-                Ok(())
+                buf.write_all(b"\n").await?;
+                self.cr = false;
+                self.new_line();
             }
-            _ => {
-                let mut start = 0;
-                for (idx, byte) in frame.iter().enumerate() {
-                    match self.escape_count {
-                        0 => self.escape_count = if *byte == b'\r' { 1 } else { 0 },
-                        1 => self.escape_count = if *byte == b'\n' { 2 } else { 0 },
-                        2 => {
-                            self.escape_count = if *byte == b'.' {
-                                3
-                            } else if *byte == b'\r' {
-                                1
-                            } else {
-                                0
+            if self.line_start {
+                buf.write_all(b".\r\n").await?;
+            } else {
+                buf.write_all(b"\r\n.\r\n").await?;
+            }
+            *self = ClientCodec {
+                line_endings: self.line_endings,
+                smuggling_protection: self.smuggling_protection,
+                max_line_length: self.max_line_length,
+                ..ClientCodec::new()
+            };
+            return Ok(());
+        }
+
+        let mut start = 0;
+        for (idx, byte) in frame.iter().enumerate() {
+            if self.cr && *byte != b'\n' {
+                self.cr = false;
+                match self.line_endings {
+                    LineEndings::Normalize => {
+                        insert(&mut buf, &frame[start..idx], b"\n").await?;
+                        start = idx;
+                        self.new_line();
+                    }
+                    LineEndings::Reject => return Err(Error::BareLineEnding(self.line)),
+                    LineEndings::PassThrough => self.bare_line_ending()?,
+                }
+            }
+            match byte {
+                b'\r' => {
+                    self.cr = true;
+                    self.line_start = false;
+                }
+                b'\n' => {
+                    if !self.cr {
+                        match self.line_endings {
+                            LineEndings::Normalize => {
+                                insert(&mut buf, &frame[start..idx], b"\r").await?;
+                                start = idx;
+                            }
+                            LineEndings::Reject => {
+                                return Err(Error::BareLineEnding(self.line));
+                            }
+                            LineEndings::PassThrough => {
+                                self.bare_line_ending()?;
+                                continue;
                             }
                         }
-                        _ => unreachable!(),
                     }
-                    if self.escape_count == 3 {
-                        self.escape_count = 0;
-                        buf.write_all(&frame[start..idx]).await?;
-                        buf.write_all(b".").await?;
-                        start = idx;
+                    self.cr = false;
+                    self.new_line();
+                }
+                b'.' if self.line_start => {
+                    if self.bare_line_start {
+                        warn!(
+                            "stuffing dot after bare CR or LF on message line {}",
+                            self.line
+                        );
                     }
+                    insert(&mut buf, &frame[start..idx], b".").await?;
+                    start = idx;
+                    self.line_start = false;
+                    self.bare_line_start = false;
+                    self.add_to_line()?;
+                }
+                _ => {
+                    self.line_start = false;
+                    self.bare_line_start = false;
+                    self.add_to_line()?;
                 }
-                buf.write_all(&frame[start..]).await?;
-                Ok(())
             }
         }
+        buf.write_all(&frame[start..]).await?;
+        Ok(())
     }
 }
 
+/// Writes `data` followed by `insertion`.
+async fn insert<W: Write + Unpin>(buf: &mut W, data: &[u8], insertion: &[u8]) -> Result<(), Error> {
+    buf.write_all(data).await?;
+    buf.write_all(insertion).await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::async_test;
 
     async_test! { test_codec, {
-        let mut codec = ClientCodec::new();
+        let mut codec = ClientCodec::new()
+            .line_endings(LineEndings::PassThrough)
+            .smuggling_protection(false);
         let mut buf: Vec<u8> = vec![];
 
         assert!(codec.encode(b"test\r\n", &mut buf).await.is_ok());
@@ -88,4 +254,164 @@ mod test {
             "test\r\n..\r\n\r\ntestte\r\n..\r\nsttesttest.test\n.test\ntest"
         );
     }}
+
+    async_test! { test_codec_normalize, {
+        let mut codec = ClientCodec::new();
+        let mut buf: Vec<u8> = vec![];
+
+        assert!(codec.encode(b".first\n", &mut buf).await.is_ok());
+        assert!(codec.encode(b"unix\n.dot\n", &mut buf).await.is_ok());
+        assert!(codec.encode(b"mac\r.dot\r", &mut buf).await.is_ok());
+        assert!(codec.encode(b".split\r", &mut buf).await.is_ok());
+        assert!(codec.encode(b"\n.crlf\r\n\n\r\r", &mut buf).await.is_ok());
+        assert!(codec.encode(b"end\r", &mut buf).await.is_ok());
+        assert!(codec.encode(b"", &mut buf).await.is_ok());
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "..first\r\nunix\r\n..dot\r\nmac\r\n..dot\r\n..split\r\n..crlf\r\n\r\n\r\n\r\nend\r\n.\r\n"
+        );
+    }}
+
+    async_test! { test_codec_reject, {
+        let mut buf: Vec<u8> = vec![];
+
+        let mut codec = ClientCodec::new().line_endings(LineEndings::Reject);
+        assert!(codec.encode(b"line\r\nline\r\n", &mut buf).await.is_ok());
+        assert!(matches!(
+            codec.encode(b"bare\nline", &mut buf).await,
+            Err(Error::BareLineEnding(3))
+        ));
+
+        let mut codec = ClientCodec::new().line_endings(LineEndings::Reject);
+        assert!(matches!(
+            codec.encode(b"bare\rline", &mut buf).await,
+            Err(Error::BareLineEnding(1))
+        ));
+
+        let mut codec = ClientCodec::new().line_endings(LineEndings::Reject);
+        assert!(codec.encode(b"line\r", &mut buf).await.is_ok());
+        assert!(matches!(
+            codec.encode(b"", &mut buf).await,
+            Err(Error::BareLineEnding(1))
+        ));
+    }}
+
+    async_test! { test_codec_end, {
+        for (frame, expected) in [
+            (&b""[..], ".\r\n"),
+            (b"test", "test\r\n.\r\n"),
+            (b"test\r\n", "test\r\n.\r\n"),
+            (b"test\r", "test\r\n.\r\n"),
+        ] {
+            let mut codec = ClientCodec::new();
+            let mut buf: Vec<u8> = vec![];
+            if !frame.is_empty() {
+                assert!(codec.encode(frame, &mut buf).await.is_ok());
+            }
+            assert!(codec.encode(b"", &mut buf).await.is_ok());
+            assert_eq!(String::from_utf8(buf).unwrap(), expected);
+        }
+    }}
+
+    /// End of data lookalikes used for SMTP smuggling.
+    const SMUGGLING_PAYLOADS: &[&[u8]] = &[
+        b"\n.\n",
+        b"\n.\r\n",
+        b"\n.\r",
+        b"\r.\r",
+        b"\r.\r\n",
+        b"\r.\n",
+        b"\r\n.\n",
+        b"\r\n.\r",
+        b"\r\n.\r\n",
+    ];
+
+    /// Checks for a dot surrounded by any combination of CR and LF.
+    fn has_end_of_data_lookalike(data: &[u8]) -> bool {
+        data.windows(3)
+            .any(|w| matches!(w, [b'\r' | b'\n', b'.', b'\r' | b'\n']))
+    }
+
+    async fn smuggle(codec: ClientCodec, payload: &[u8], split: bool) -> Result<Vec<u8>, Error> {
+        let mut codec = codec;
+        let mut buf: Vec<u8> = vec![];
+        let message = [
+            &b"Subject: test\r\n\r\nHello"[..],
+            payload,
+            b"MAIL FROM:<admin@example.org>\r\nRCPT TO:<victim@example.org>\r\n",
+        ]
+        .concat();
+        if split {
+            for byte in message.chunks(1) {
+                codec.encode(byte, &mut buf).await?;
+            }
+        } else {
+            codec.encode(&message, &mut buf).await?;
+        }
+        Ok(buf)
+    }
+
+    async_test! { test_codec_smuggling, {
+        for payload in SMUGGLING_PAYLOADS {
+            for split in [false, true] {
+                for codec in [
+                    ClientCodec::new(),
+                    ClientCodec::new().line_endings(LineEndings::PassThrough),
+                ] {
+                    let buf = smuggle(codec, payload, split).await.unwrap();
+                    assert!(
+                        !has_end_of_data_lookalike(&buf),
+                        "{:?} with {:?}",
+                        String::from_utf8_lossy(&buf),
+                        codec
+                    );
+                }
+            }
+        }
+    }}
+
+    async_test! { test_codec_smuggling_reject, {
+        for payload in SMUGGLING_PAYLOADS {
+            let codec = ClientCodec::new().line_endings(LineEndings::Reject);
+            let res = smuggle(codec, payload, false).await;
+            // Only the canonical sequence contains no bare CR or LF.
+            assert_eq!(res.is_err(), *payload != &b"\r\n.\r\n"[..]);
+        }
+    }}
+
+    async_test! { test_codec_smuggling_unprotected, {
+        let codec = ClientCodec::new()
+            .line_endings(LineEndings::PassThrough)
+            .smuggling_protection(false);
+        let buf = smuggle(codec, b"\n.\n", false).await.unwrap();
+        assert!(has_end_of_data_lookalike(&buf));
+    }}
+
+    async_test! { test_codec_max_line_length, {
+        let line = [b'a'; MAX_LINE_LENGTH];
+        let mut buf: Vec<u8> = vec![];
+
+        let mut codec = ClientCodec::new().max_line_length(Some(MAX_LINE_LENGTH));
+        assert!(codec.encode(b"first\r\n.", &mut buf).await.is_ok());
+        assert!(codec.encode(&line[1..], &mut buf).await.is_ok());
+        assert!(codec.encode(b"\n", &mut buf).await.is_ok());
+        assert!(codec.encode(&line, &mut buf).await.is_ok());
+        assert!(matches!(
+            codec.encode(b"a\r\n", &mut buf).await,
+            Err(Error::LineTooLong(3))
+        ));
+
+        let mut codec = ClientCodec::new()
+            .line_endings(LineEndings::PassThrough)
+            .max_line_length(Some(MAX_LINE_LENGTH));
+        assert!(codec.encode(&line, &mut buf).await.is_ok());
+        assert!(matches!(
+            codec.encode(b"\n", &mut buf).await,
+            Err(Error::LineTooLong(1))
+        ));
+
+        let mut codec = ClientCodec::new();
+        assert!(codec.encode(&line, &mut buf).await.is_ok());
+        assert!(codec.encode(&line, &mut buf).await.is_ok());
+    }}
 }
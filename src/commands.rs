@@ -2,12 +2,28 @@
 
 use crate::authentication::{Credentials, Mechanism};
 use crate::error::Error;
-use crate::extension::{ClientId, MailParameter, RcptParameter};
+use crate::extension::{
+    hold_until_interval, ClientId, DeliverByMode, MailBodyParameter, MailParameter, RcptParameter,
+    MAX_DELIVER_BY,
+};
 use crate::response::Response;
+use crate::util::{decode_xtext, StrictXText};
 use crate::EmailAddress;
 use log::debug;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case, take_till, take_till1, take_while1},
+    character::complete::char,
+    combinator::{all_consuming, map, opt, value},
+    error::{ErrorKind, ParseError},
+    multi::{many0, many1, separated_list1},
+    sequence::{delimited, preceded, terminated, tuple},
+    IResult,
+};
 use std::convert::AsRef;
 use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
 
 /// EHLO command
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -21,11 +37,93 @@ impl Display for EhloCommand {
     }
 }
 
+impl FromStr for EhloCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EhloCommand, Error> {
+        parse_command(s, parse_ehlo)
+    }
+}
+
 impl EhloCommand {
     /// Creates a EHLO command
     pub fn new(client_id: ClientId) -> EhloCommand {
         EhloCommand { client_id }
     }
+
+    /// Returns the client identifier
+    pub fn client_id(&self) -> &ClientId {
+        &self.client_id
+    }
+}
+
+/// HELO command
+///
+/// Only used with servers which do not support EHLO, see
+/// [RFC 5321, section 4.1.1.1](https://tools.ietf.org/html/rfc5321#section-4.1.1.1)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct HeloCommand {
+    client_id: ClientId,
+}
+
+impl Display for HeloCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "HELO {}\r\n", self.client_id)
+    }
+}
+
+impl FromStr for HeloCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<HeloCommand, Error> {
+        parse_command(s, parse_helo)
+    }
+}
+
+impl HeloCommand {
+    /// Creates a HELO command
+    pub fn new(client_id: ClientId) -> HeloCommand {
+        HeloCommand { client_id }
+    }
+
+    /// Returns the client identifier
+    pub fn client_id(&self) -> &ClientId {
+        &self.client_id
+    }
+}
+
+/// LHLO command
+///
+/// Replaces EHLO in LMTP, see [RFC 2033](https://tools.ietf.org/html/rfc2033)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct LhloCommand {
+    client_id: ClientId,
+}
+
+impl Display for LhloCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "LHLO {}\r\n", self.client_id)
+    }
+}
+
+impl FromStr for LhloCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LhloCommand, Error> {
+        parse_command(s, parse_lhlo)
+    }
+}
+
+impl LhloCommand {
+    /// Creates a LHLO command
+    pub fn new(client_id: ClientId) -> LhloCommand {
+        LhloCommand { client_id }
+    }
+
+    /// Returns the client identifier
+    pub fn client_id(&self) -> &ClientId {
+        &self.client_id
+    }
 }
 
 /// STARTTLS command
@@ -38,6 +136,14 @@ impl Display for StarttlsCommand {
     }
 }
 
+impl FromStr for StarttlsCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<StarttlsCommand, Error> {
+        parse_command(s, parse_starttls)
+    }
+}
+
 /// MAIL command
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct MailCommand {
@@ -59,11 +165,29 @@ impl Display for MailCommand {
     }
 }
 
+impl FromStr for MailCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<MailCommand, Error> {
+        parse_command(s, parse_mail)
+    }
+}
+
 impl MailCommand {
     /// Creates a MAIL command
     pub fn new(sender: Option<EmailAddress>, parameters: Vec<MailParameter>) -> MailCommand {
         MailCommand { sender, parameters }
     }
+
+    /// Returns the sender, or `None` for the null reverse-path
+    pub fn sender(&self) -> Option<&EmailAddress> {
+        self.sender.as_ref()
+    }
+
+    /// Returns the parameters
+    pub fn parameters(&self) -> &[MailParameter] {
+        &self.parameters
+    }
 }
 
 /// RCPT command
@@ -83,6 +207,14 @@ impl Display for RcptCommand {
     }
 }
 
+impl FromStr for RcptCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RcptCommand, Error> {
+        parse_command(s, parse_rcpt)
+    }
+}
+
 impl RcptCommand {
     /// Creates an RCPT command
     pub fn new(recipient: EmailAddress, parameters: Vec<RcptParameter>) -> RcptCommand {
@@ -91,6 +223,16 @@ impl RcptCommand {
             parameters,
         }
     }
+
+    /// Returns the recipient
+    pub fn recipient(&self) -> &EmailAddress {
+        &self.recipient
+    }
+
+    /// Returns the parameters
+    pub fn parameters(&self) -> &[RcptParameter] {
+        &self.parameters
+    }
 }
 
 /// DATA command
@@ -103,6 +245,14 @@ impl Display for DataCommand {
     }
 }
 
+impl FromStr for DataCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DataCommand, Error> {
+        parse_command(s, parse_data)
+    }
+}
+
 /// QUIT command
 #[derive(PartialEq, Eq, Clone, Debug, Copy)]
 pub struct QuitCommand;
@@ -113,6 +263,14 @@ impl Display for QuitCommand {
     }
 }
 
+impl FromStr for QuitCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<QuitCommand, Error> {
+        parse_command(s, parse_quit)
+    }
+}
+
 /// NOOP command
 #[derive(PartialEq, Eq, Clone, Debug, Copy)]
 pub struct NoopCommand;
@@ -123,6 +281,14 @@ impl Display for NoopCommand {
     }
 }
 
+impl FromStr for NoopCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<NoopCommand, Error> {
+        parse_command(s, parse_noop)
+    }
+}
+
 /// HELP command
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct HelpCommand {
@@ -139,6 +305,14 @@ impl Display for HelpCommand {
     }
 }
 
+impl FromStr for HelpCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<HelpCommand, Error> {
+        parse_command(s, parse_help)
+    }
+}
+
 impl HelpCommand {
     /// Creates an HELP command
     pub fn new(argument: Option<String>) -> HelpCommand {
@@ -158,6 +332,14 @@ impl Display for VrfyCommand {
     }
 }
 
+impl FromStr for VrfyCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<VrfyCommand, Error> {
+        parse_command(s, parse_vrfy)
+    }
+}
+
 impl VrfyCommand {
     /// Creates a VRFY command
     pub fn new(argument: String) -> VrfyCommand {
@@ -177,6 +359,14 @@ impl Display for ExpnCommand {
     }
 }
 
+impl FromStr for ExpnCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ExpnCommand, Error> {
+        parse_command(s, parse_expn)
+    }
+}
+
 impl ExpnCommand {
     /// Creates an EXPN command
     pub fn new(argument: String) -> ExpnCommand {
@@ -194,7 +384,18 @@ impl Display for RsetCommand {
     }
 }
 
+impl FromStr for RsetCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RsetCommand, Error> {
+        parse_command(s, parse_rset)
+    }
+}
+
 /// AUTH command
+///
+/// Parsing an AUTH command recovers the credentials from the initial response. For
+/// mechanisms without initial response, like LOGIN, the parsed credentials are empty.
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct AuthCommand {
     mechanism: Mechanism,
@@ -227,6 +428,14 @@ impl Display for AuthCommand {
     }
 }
 
+impl FromStr for AuthCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AuthCommand, Error> {
+        parse_command(s, parse_auth)
+    }
+}
+
 impl AuthCommand {
     /// Creates an AUTH command (from a challenge if provided)
     pub fn new(
@@ -277,10 +486,634 @@ impl AuthCommand {
     }
 }
 
+/// Attribute of the original SMTP client, sent by a trusted proxy with XCLIENT or XFORWARD
+///
+/// See the [Postfix documentation](https://www.postfix.org/XCLIENT_README.html).
+/// `Name`, `Proto`, `Helo` and `Login` values are xtext-encoded.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ClientAttribute {
+    /// `ADDR`, the client IP address
+    Addr(IpAddr),
+    /// `NAME`, the client hostname
+    Name(String),
+    /// `PORT`, the client TCP port
+    Port(u16),
+    /// `PROTO`, `SMTP` or `ESMTP`
+    Proto(String),
+    /// `HELO`, the name sent by the client with HELO or EHLO
+    Helo(String),
+    /// `LOGIN`, the SASL login name, XCLIENT only
+    Login(String),
+    /// `DESTADDR`, the server IP address the client connected to, XCLIENT only
+    DestAddr(IpAddr),
+}
+
+impl ClientAttribute {
+    /// Returns the attribute name
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ClientAttribute::Addr(_) => "ADDR",
+            ClientAttribute::Name(_) => "NAME",
+            ClientAttribute::Port(_) => "PORT",
+            ClientAttribute::Proto(_) => "PROTO",
+            ClientAttribute::Helo(_) => "HELO",
+            ClientAttribute::Login(_) => "LOGIN",
+            ClientAttribute::DestAddr(_) => "DESTADDR",
+        }
+    }
+}
+
+/// Formats an address, with the `IPV6:` prefix used by Postfix for IPv6.
+fn fmt_attribute_addr(f: &mut Formatter, addr: &IpAddr) -> fmt::Result {
+    match addr {
+        IpAddr::V4(addr) => write!(f, "{addr}"),
+        IpAddr::V6(addr) => write!(f, "IPV6:{addr}"),
+    }
+}
+
+impl Display for ClientAttribute {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}=", self.name())?;
+        match *self {
+            ClientAttribute::Addr(ref addr) | ClientAttribute::DestAddr(ref addr) => {
+                fmt_attribute_addr(f, addr)
+            }
+            ClientAttribute::Port(port) => write!(f, "{port}"),
+            ClientAttribute::Name(ref value)
+            | ClientAttribute::Proto(ref value)
+            | ClientAttribute::Helo(ref value)
+            | ClientAttribute::Login(ref value) => write!(f, "{}", StrictXText(value)),
+        }
+    }
+}
+
+/// XCLIENT command
+///
+/// It overrides the client attributes seen by the server, which then resets the session
+/// and sends a new greeting.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct XclientCommand {
+    attributes: Vec<ClientAttribute>,
+}
+
+impl Display for XclientCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("XCLIENT")?;
+        for attribute in &self.attributes {
+            write!(f, " {attribute}")?;
+        }
+        f.write_str("\r\n")
+    }
+}
+
+impl FromStr for XclientCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<XclientCommand, Error> {
+        parse_command(s, parse_xclient)
+    }
+}
+
+impl XclientCommand {
+    /// Creates an XCLIENT command
+    pub fn new(attributes: Vec<ClientAttribute>) -> XclientCommand {
+        XclientCommand { attributes }
+    }
+
+    /// Returns the attributes
+    pub fn attributes(&self) -> &[ClientAttribute] {
+        &self.attributes
+    }
+}
+
+/// XFORWARD command
+///
+/// It passes the client attributes to the server for logging, without resetting the session.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct XforwardCommand {
+    attributes: Vec<ClientAttribute>,
+}
+
+impl Display for XforwardCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("XFORWARD")?;
+        for attribute in &self.attributes {
+            write!(f, " {attribute}")?;
+        }
+        f.write_str("\r\n")
+    }
+}
+
+impl FromStr for XforwardCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<XforwardCommand, Error> {
+        parse_command(s, parse_xforward)
+    }
+}
+
+impl XforwardCommand {
+    /// Creates an XFORWARD command
+    pub fn new(attributes: Vec<ClientAttribute>) -> XforwardCommand {
+        XforwardCommand { attributes }
+    }
+
+    /// Returns the attributes
+    pub fn attributes(&self) -> &[ClientAttribute] {
+        &self.attributes
+    }
+}
+
+/// Node whose queue is started by ETRN
+///
+/// [RFC 1985, section 3](https://tools.ietf.org/html/rfc1985#section-3)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum EtrnNode {
+    /// Messages for the domain
+    Domain(String),
+    /// Messages for the domain and its subdomains, `@domain`
+    Subdomains(String),
+    /// Messages in the named queue, `#queue`
+    Queue(String),
+}
+
+impl Display for EtrnNode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            EtrnNode::Domain(ref domain) => f.write_str(domain),
+            EtrnNode::Subdomains(ref domain) => write!(f, "@{domain}"),
+            EtrnNode::Queue(ref queue) => write!(f, "#{queue}"),
+        }
+    }
+}
+
+/// ETRN command
+///
+/// [RFC 1985, section 3](https://tools.ietf.org/html/rfc1985#section-3)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct EtrnCommand {
+    node: EtrnNode,
+}
+
+impl Display for EtrnCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ETRN {}\r\n", self.node)
+    }
+}
+
+impl FromStr for EtrnCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EtrnCommand, Error> {
+        parse_command(s, parse_etrn)
+    }
+}
+
+impl EtrnCommand {
+    /// Creates an ETRN command
+    pub fn new(node: EtrnNode) -> EtrnCommand {
+        EtrnCommand { node }
+    }
+
+    /// Returns the node
+    pub fn node(&self) -> &EtrnNode {
+        &self.node
+    }
+}
+
+/// ATRN command
+///
+/// Without domains, the server chooses the domains for the authenticated client.
+///
+/// [RFC 2645, section 4.2](https://tools.ietf.org/html/rfc2645#section-4.2)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct AtrnCommand {
+    domains: Vec<String>,
+}
+
+impl Display for AtrnCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("ATRN")?;
+        if !self.domains.is_empty() {
+            write!(f, " {}", self.domains.join(","))?;
+        }
+        f.write_str("\r\n")
+    }
+}
+
+impl FromStr for AtrnCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AtrnCommand, Error> {
+        parse_command(s, parse_atrn)
+    }
+}
+
+impl AtrnCommand {
+    /// Creates an ATRN command
+    pub fn new(domains: Vec<String>) -> AtrnCommand {
+        AtrnCommand { domains }
+    }
+
+    /// Returns the domains
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+}
+
+// Parsers
+
+/// Error of the command parsers
+///
+/// It carries the reason why an argument is invalid up to the caller.
+#[derive(Debug)]
+struct CommandError(Error);
+
+impl<'a> ParseError<&'a str> for CommandError {
+    fn from_error_kind(_input: &'a str, kind: ErrorKind) -> Self {
+        CommandError(Error::Parsing(kind))
+    }
+
+    fn append(_input: &'a str, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+type ParseResult<'a, O> = IResult<&'a str, O, CommandError>;
+
+/// Parses a whole command line, with an optional CRLF.
+fn parse_command<'a, O>(
+    i: &'a str,
+    parser: impl FnMut(&'a str) -> ParseResult<'a, O>,
+) -> Result<O, Error> {
+    all_consuming(terminated(parser, opt(tag("\r\n"))))(i)
+        .map(|(_, command)| command)
+        .map_err(|err| match err {
+            nom::Err::Error(CommandError(err)) | nom::Err::Failure(CommandError(err)) => err,
+            nom::Err::Incomplete(_) => Error::Parsing(ErrorKind::Complete),
+        })
+}
+
+/// Maps the output of a parser with a fallible function.
+///
+/// Unlike `map_res`, the parser fails without backtracking and keeps the error of the
+/// function, so an invalid argument is reported as such.
+fn try_map<'a, O1, O2>(
+    mut parser: impl FnMut(&'a str) -> ParseResult<'a, O1>,
+    mut f: impl FnMut(O1) -> Result<O2, Error>,
+) -> impl FnMut(&'a str) -> ParseResult<'a, O2> {
+    move |i| {
+        let (i, output) = parser(i)?;
+        f(output)
+            .map(|output| (i, output))
+            .map_err(|err| nom::Err::Failure(CommandError(err)))
+    }
+}
+
+fn is_line_end(c: char) -> bool {
+    c == '\r' || c == '\n'
+}
+
+/// Parses the rest of the line.
+fn parse_text(i: &str) -> ParseResult<'_, &str> {
+    take_till(is_line_end)(i)
+}
+
+fn parse_client_id(i: &str) -> ParseResult<'_, ClientId> {
+    try_map(take_till1(|c| c == ' ' || is_line_end(c)), str::parse)(i)
+}
+
+fn parse_ehlo(i: &str) -> ParseResult<'_, EhloCommand> {
+    map(
+        preceded(tag_no_case("EHLO "), parse_client_id),
+        EhloCommand::new,
+    )(i)
+}
+
+fn parse_helo(i: &str) -> ParseResult<'_, HeloCommand> {
+    map(
+        preceded(tag_no_case("HELO "), parse_client_id),
+        HeloCommand::new,
+    )(i)
+}
+
+fn parse_lhlo(i: &str) -> ParseResult<'_, LhloCommand> {
+    map(
+        preceded(tag_no_case("LHLO "), parse_client_id),
+        LhloCommand::new,
+    )(i)
+}
+
+fn parse_starttls(i: &str) -> ParseResult<'_, StarttlsCommand> {
+    value(StarttlsCommand, tag_no_case("STARTTLS"))(i)
+}
+
+/// Parses `<path>`, `None` for the null path.
+///
+/// A space before the path is tolerated.
+fn parse_path(i: &str) -> ParseResult<'_, Option<EmailAddress>> {
+    preceded(
+        opt(char(' ')),
+        delimited(
+            char('<'),
+            try_map(take_till(|c| c == '>'), |path: &str| {
+                if path.is_empty() {
+                    Ok(None)
+                } else {
+                    EmailAddress::new(path.to_string())
+                        .map(Some)
+                        .map_err(|_| Error::CommandParsing("Invalid address"))
+                }
+            }),
+            char('>'),
+        ),
+    )(i)
+}
+
+/// Parses ` keyword[=value]`.
+fn parse_parameter(i: &str) -> ParseResult<'_, (&str, Option<&str>)> {
+    preceded(
+        char(' '),
+        tuple((
+            take_while1(|c: char| c.is_ascii_alphanumeric() || c == '-'),
+            opt(preceded(
+                char('='),
+                take_till(|c| c == ' ' || is_line_end(c)),
+            )),
+        )),
+    )(i)
+}
+
+/// Decodes the value of a parameter.
+///
+/// Only the values of the parameters defined as xtext are decoded, other values are kept
+/// as sent.
+fn decode_value(keyword: &str, value: Option<&str>) -> Result<Option<String>, Error> {
+    match value {
+        Some(value)
+            if ["ENVID", "ORCPT", "AUTH"]
+                .iter()
+                .any(|xtext| keyword.eq_ignore_ascii_case(xtext)) =>
+        {
+            decode_xtext(value).map(Some)
+        }
+        value => Ok(value.map(ToString::to_string)),
+    }
+}
+
+fn parse_mail_parameter(i: &str) -> ParseResult<'_, MailParameter> {
+    try_map(parse_parameter, |(keyword, value)| {
+        Ok::<_, Error>(match (keyword.to_ascii_uppercase().as_str(), value) {
+            ("BODY", Some(body)) if body.eq_ignore_ascii_case("7BIT") => {
+                MailParameter::Body(MailBodyParameter::SevenBit)
+            }
+            ("BODY", Some(body)) if body.eq_ignore_ascii_case("8BITMIME") => {
+                MailParameter::Body(MailBodyParameter::EightBitMime)
+            }
+            ("SIZE", Some(size)) => MailParameter::Size(
+                size.parse()
+                    .map_err(|_| Error::CommandParsing("Invalid SIZE"))?,
+            ),
+            ("SMTPUTF8", None) => MailParameter::SmtpUtfEight,
+            ("REQUIRETLS", None) => MailParameter::RequireTls,
+            ("MT-PRIORITY", Some(priority)) => MailParameter::MtPriority(
+                priority
+                    .parse()
+                    .map_err(|_| Error::CommandParsing("Invalid MT-PRIORITY"))?,
+            ),
+            ("BY", Some(by)) => parse_deliver_by(by)?,
+            ("HOLDFOR", Some(seconds)) => MailParameter::HoldFor(
+                seconds
+                    .parse()
+                    .map_err(|_| Error::CommandParsing("Invalid HOLDFOR"))?,
+            ),
+            ("HOLDUNTIL", Some(date)) => MailParameter::HoldUntil(
+                humantime::parse_rfc3339(date)
+                    .ok()
+                    .filter(|&date| hold_until_interval(date).is_some())
+                    .ok_or(Error::CommandParsing("Invalid HOLDUNTIL"))?,
+            ),
+            _ => MailParameter::Other {
+                keyword: keyword.to_string(),
+                value: decode_value(keyword, value)?,
+            },
+        })
+    })(i)
+}
+
+/// Parses the value of the `BY` parameter, `<seconds>;<R|N>[T]`.
+fn parse_deliver_by(by: &str) -> Result<MailParameter, Error> {
+    let invalid = || Error::CommandParsing("Invalid BY");
+    let (seconds, mode) = by.split_once(';').ok_or_else(invalid)?;
+    let (mode, trace) = match mode.to_ascii_uppercase().as_str() {
+        "R" => (DeliverByMode::Return, false),
+        "RT" => (DeliverByMode::Return, true),
+        "N" => (DeliverByMode::Notify, false),
+        "NT" => (DeliverByMode::Notify, true),
+        _ => return Err(invalid()),
+    };
+    let seconds = seconds.parse().map_err(|_| invalid())?;
+    if !(-MAX_DELIVER_BY..=MAX_DELIVER_BY).contains(&seconds) {
+        return Err(invalid());
+    }
+    Ok(MailParameter::DeliverBy {
+        seconds,
+        mode,
+        trace,
+    })
+}
+
+fn parse_rcpt_parameter(i: &str) -> ParseResult<'_, RcptParameter> {
+    try_map(parse_parameter, |(keyword, value)| {
+        Ok::<_, Error>(RcptParameter::Other {
+            keyword: keyword.to_string(),
+            value: decode_value(keyword, value)?,
+        })
+    })(i)
+}
+
+fn parse_mail(i: &str) -> ParseResult<'_, MailCommand> {
+    map(
+        preceded(
+            tag_no_case("MAIL FROM:"),
+            tuple((parse_path, many0(parse_mail_parameter))),
+        ),
+        |(sender, parameters)| MailCommand::new(sender, parameters),
+    )(i)
+}
+
+fn parse_rcpt(i: &str) -> ParseResult<'_, RcptCommand> {
+    try_map(
+        preceded(
+            tag_no_case("RCPT TO:"),
+            tuple((parse_path, many0(parse_rcpt_parameter))),
+        ),
+        |(recipient, parameters)| {
+            recipient
+                .map(|recipient| RcptCommand::new(recipient, parameters))
+                .ok_or(Error::CommandParsing("Missing recipient"))
+        },
+    )(i)
+}
+
+fn parse_data(i: &str) -> ParseResult<'_, DataCommand> {
+    value(DataCommand, tag_no_case("DATA"))(i)
+}
+
+fn parse_quit(i: &str) -> ParseResult<'_, QuitCommand> {
+    value(QuitCommand, tag_no_case("QUIT"))(i)
+}
+
+fn parse_noop(i: &str) -> ParseResult<'_, NoopCommand> {
+    value(NoopCommand, tag_no_case("NOOP"))(i)
+}
+
+fn parse_help(i: &str) -> ParseResult<'_, HelpCommand> {
+    map(
+        preceded(tag_no_case("HELP"), opt(preceded(char(' '), parse_text))),
+        |argument| HelpCommand::new(argument.map(ToString::to_string)),
+    )(i)
+}
+
+fn parse_vrfy(i: &str) -> ParseResult<'_, VrfyCommand> {
+    map(preceded(tag_no_case("VRFY "), parse_text), |argument| {
+        VrfyCommand::new(argument.to_string())
+    })(i)
+}
+
+fn parse_expn(i: &str) -> ParseResult<'_, ExpnCommand> {
+    map(preceded(tag_no_case("EXPN "), parse_text), |argument| {
+        ExpnCommand::new(argument.to_string())
+    })(i)
+}
+
+fn parse_rset(i: &str) -> ParseResult<'_, RsetCommand> {
+    value(RsetCommand, tag_no_case("RSET"))(i)
+}
+
+fn parse_mechanism(i: &str) -> ParseResult<'_, Mechanism> {
+    alt((
+        value(Mechanism::Plain, tag_no_case("PLAIN")),
+        value(Mechanism::Login, tag_no_case("LOGIN")),
+        value(Mechanism::Xoauth2, tag_no_case("XOAUTH2")),
+    ))(i)
+}
+
+fn parse_auth(i: &str) -> ParseResult<'_, AuthCommand> {
+    try_map(
+        preceded(
+            tag_no_case("AUTH "),
+            tuple((parse_mechanism, opt(preceded(char(' '), parse_text)))),
+        ),
+        |(mechanism, encoded_response)| {
+            let response = encoded_response
+                .map(|encoded| String::from_utf8(base64::decode(encoded)?).map_err(Error::from))
+                .transpose()?;
+            Ok::<_, Error>(AuthCommand {
+                mechanism,
+                credentials: mechanism.credentials(response.as_deref())?,
+                challenge: None,
+                response,
+            })
+        },
+    )(i)
+}
+
+/// Parses an address, with an optional `IPV6:` prefix.
+fn parse_attribute_addr(addr: &str) -> Result<IpAddr, Error> {
+    let addr = match addr.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("IPV6:") => &addr[5..],
+        _ => addr,
+    };
+    addr.parse()
+        .map_err(|_| Error::CommandParsing("Invalid address"))
+}
+
+fn parse_client_attribute(i: &str) -> ParseResult<'_, ClientAttribute> {
+    try_map(parse_parameter, |(name, value)| {
+        let value = value.ok_or(Error::CommandParsing("Missing attribute value"))?;
+        Ok::<_, Error>(match name.to_ascii_uppercase().as_str() {
+            "ADDR" => ClientAttribute::Addr(parse_attribute_addr(value)?),
+            "NAME" => ClientAttribute::Name(decode_xtext(value)?),
+            "PORT" => ClientAttribute::Port(
+                value
+                    .parse()
+                    .map_err(|_| Error::CommandParsing("Invalid PORT"))?,
+            ),
+            "PROTO" => ClientAttribute::Proto(decode_xtext(value)?),
+            "HELO" => ClientAttribute::Helo(decode_xtext(value)?),
+            "LOGIN" => ClientAttribute::Login(decode_xtext(value)?),
+            "DESTADDR" => ClientAttribute::DestAddr(parse_attribute_addr(value)?),
+            _ => return Err(Error::CommandParsing("Unknown attribute")),
+        })
+    })(i)
+}
+
+fn parse_xclient(i: &str) -> ParseResult<'_, XclientCommand> {
+    map(
+        preceded(tag_no_case("XCLIENT"), many1(parse_client_attribute)),
+        XclientCommand::new,
+    )(i)
+}
+
+fn parse_etrn(i: &str) -> ParseResult<'_, EtrnCommand> {
+    try_map(
+        preceded(
+            tag_no_case("ETRN "),
+            take_till1(|c| c == ' ' || is_line_end(c)),
+        ),
+        |node: &str| {
+            let node = match (node.strip_prefix('@'), node.strip_prefix('#')) {
+                (Some(domain), _) => EtrnNode::Subdomains(domain.to_string()),
+                (_, Some(queue)) => EtrnNode::Queue(queue.to_string()),
+                _ => EtrnNode::Domain(node.to_string()),
+            };
+            match node {
+                EtrnNode::Subdomains(ref name) | EtrnNode::Queue(ref name) if name.is_empty() => {
+                    Err(Error::CommandParsing("Empty ETRN node"))
+                }
+                node => Ok(EtrnCommand::new(node)),
+            }
+        },
+    )(i)
+}
+
+fn parse_atrn(i: &str) -> ParseResult<'_, AtrnCommand> {
+    map(
+        preceded(
+            tag_no_case("ATRN"),
+            opt(preceded(
+                char(' '),
+                separated_list1(
+                    char(','),
+                    take_till1(|c| c == ',' || c == ' ' || is_line_end(c)),
+                ),
+            )),
+        ),
+        |domains: Option<Vec<&str>>| {
+            AtrnCommand::new(
+                domains
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            )
+        },
+    )(i)
+}
+
+fn parse_xforward(i: &str) -> ParseResult<'_, XforwardCommand> {
+    map(
+        preceded(tag_no_case("XFORWARD"), many1(parse_client_attribute)),
+        XforwardCommand::new,
+    )(i)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::extension::MailBodyParameter;
+    use crate::extension::MAX_HOLD_UNTIL;
+    use proptest::prelude::*;
 
     #[test]
     fn test_display() {
@@ -295,11 +1128,22 @@ mod test {
             keyword: "TEST".to_string(),
             value: Some("value".to_string()),
         };
+        assert_eq!(
+            format!("{}", HeloCommand::new(id.clone())),
+            "HELO localhost\r\n"
+        );
         assert_eq!(format!("{}", EhloCommand::new(id)), "EHLO localhost\r\n");
         assert_eq!(
             format!("{}", EhloCommand::new(id_ipv4)),
             "EHLO [127.0.0.1]\r\n"
         );
+        assert_eq!(
+            format!(
+                "{}",
+                LhloCommand::new(ClientId::new("localhost".to_string()))
+            ),
+            "LHLO localhost\r\n"
+        );
         assert_eq!(
             format!("{}", MailCommand::new(Some(email.clone()), vec![])),
             "MAIL FROM:<test@example.com>\r\n"
@@ -370,4 +1214,266 @@ mod test {
             "AUTH LOGIN\r\n"
         );
     }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(
+            "ehlo [127.0.0.1]".parse::<EhloCommand>().unwrap(),
+            EhloCommand::new(ClientId::Ipv4(std::net::Ipv4Addr::new(127, 0, 0, 1)))
+        );
+        assert_eq!(
+            "MAIL FROM: <> ENVID=a+2Bb\r\n"
+                .parse::<MailCommand>()
+                .unwrap(),
+            MailCommand::new(
+                None,
+                vec![MailParameter::Other {
+                    keyword: "ENVID".to_string(),
+                    value: Some("a+b".to_string()),
+                }]
+            )
+        );
+        assert_eq!(
+            "RCPT TO:<user@example.org> X-TAG=a+2Bb"
+                .parse::<RcptCommand>()
+                .unwrap(),
+            RcptCommand::new(
+                "user@example.org".parse().unwrap(),
+                vec![RcptParameter::Other {
+                    keyword: "X-TAG".to_string(),
+                    value: Some("a+2Bb".to_string()),
+                }]
+            )
+        );
+        assert_eq!(
+            "AUTH LOGIN".parse::<AuthCommand>().unwrap().mechanism,
+            Mechanism::Login
+        );
+        assert!("EHLO".parse::<EhloCommand>().is_err());
+        assert!("MAIL FROM:user@example.org".parse::<MailCommand>().is_err());
+        assert_eq!(
+            "MAIL FROM:<> MT-PRIORITY=-2 BY=120;nt"
+                .parse::<MailCommand>()
+                .unwrap()
+                .parameters(),
+            [
+                MailParameter::MtPriority(-2),
+                MailParameter::DeliverBy {
+                    seconds: 120,
+                    mode: DeliverByMode::Notify,
+                    trace: true,
+                }
+            ]
+        );
+        assert!(matches!(
+            "MAIL FROM:<> BY=120;X".parse::<MailCommand>(),
+            Err(Error::CommandParsing("Invalid BY"))
+        ));
+        assert!(matches!(
+            "MAIL FROM:<> BY=1000000000;N".parse::<MailCommand>(),
+            Err(Error::CommandParsing("Invalid BY"))
+        ));
+        assert!(matches!(
+            "MAIL FROM:<> SIZE=42 HOLDFOR=soon".parse::<MailCommand>(),
+            Err(Error::CommandParsing("Invalid HOLDFOR"))
+        ));
+        assert!(matches!(
+            "MAIL FROM:<> HOLDUNTIL=2000-01-01T00:00:00Z".parse::<MailCommand>(),
+            Err(Error::CommandParsing("Invalid HOLDUNTIL"))
+        ));
+        assert!(matches!(
+            "MAIL FROM:<> ENVID=a+2".parse::<MailCommand>(),
+            Err(Error::InvalidXText(_))
+        ));
+        assert!(matches!(
+            "RCPT TO:<>".parse::<RcptCommand>(),
+            Err(Error::CommandParsing("Missing recipient"))
+        ));
+        assert!(matches!(
+            "MAIL FROM:<user@example.org>".parse::<DataCommand>(),
+            Err(Error::Parsing(_))
+        ));
+        assert!("DATA extra".parse::<DataCommand>().is_err());
+        assert!("AUTH CRAM-MD5".parse::<AuthCommand>().is_err());
+    }
+
+    fn email_address() -> impl Strategy<Value = EmailAddress> {
+        "[a-z0-9._+-]{1,16}@[a-z0-9.-]{1,16}".prop_map(|s| EmailAddress::new(s).unwrap())
+    }
+
+    fn client_id() -> impl Strategy<Value = ClientId> {
+        prop_oneof![
+            "[a-z0-9-]{1,16}(\\.[a-z0-9-]{1,16}){0,3}".prop_map(ClientId::Domain),
+            any::<std::net::Ipv4Addr>().prop_map(ClientId::Ipv4),
+            any::<std::net::Ipv6Addr>().prop_map(ClientId::Ipv6),
+        ]
+    }
+
+    fn mail_parameter() -> impl Strategy<Value = MailParameter> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        prop_oneof![
+            Just(MailParameter::Body(MailBodyParameter::SevenBit)),
+            Just(MailParameter::Body(MailBodyParameter::EightBitMime)),
+            any::<usize>().prop_map(MailParameter::Size),
+            Just(MailParameter::SmtpUtfEight),
+            Just(MailParameter::RequireTls),
+            any::<i8>().prop_map(MailParameter::MtPriority),
+            any::<u64>().prop_map(MailParameter::HoldFor),
+            (now + 3600..=MAX_HOLD_UNTIL.as_secs()).prop_map(|seconds| MailParameter::HoldUntil(
+                std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds)
+            )),
+            (
+                -MAX_DELIVER_BY..=MAX_DELIVER_BY,
+                prop_oneof![Just(DeliverByMode::Return), Just(DeliverByMode::Notify)],
+                any::<bool>()
+            )
+                .prop_map(|(seconds, mode, trace)| MailParameter::DeliverBy {
+                    seconds,
+                    mode,
+                    trace
+                }),
+            any::<String>().prop_map(|value| MailParameter::Other {
+                keyword: "ENVID".to_string(),
+                value: Some(value),
+            }),
+            ("X-[A-Z0-9]{1,8}", proptest::option::of("[!-*,-<>-~]{1,16}"))
+                .prop_map(|(keyword, value)| MailParameter::Other { keyword, value }),
+        ]
+    }
+
+    fn rcpt_parameter() -> impl Strategy<Value = RcptParameter> {
+        prop_oneof![
+            any::<String>().prop_map(|value| RcptParameter::Other {
+                keyword: "ORCPT".to_string(),
+                value: Some(value),
+            }),
+            ("X-[A-Z0-9]{1,8}", proptest::option::of("[!-*,-<>-~]{1,16}"))
+                .prop_map(|(keyword, value)| RcptParameter::Other { keyword, value }),
+        ]
+    }
+
+    fn round_trip<T: Display + FromStr<Err = Error> + PartialEq + fmt::Debug>(
+        command: T,
+    ) -> Result<(), TestCaseError> {
+        let parsed = command.to_string().parse::<T>();
+        prop_assert!(parsed.is_ok(), "{:?} for {}", parsed, command);
+        prop_assert_eq!(parsed.unwrap(), command);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip_hello(client_id in client_id()) {
+            round_trip(EhloCommand::new(client_id.clone()))?;
+            round_trip(HeloCommand::new(client_id.clone()))?;
+            round_trip(LhloCommand::new(client_id))?;
+        }
+
+        #[test]
+        fn test_round_trip_mail(
+            sender in proptest::option::of(email_address()),
+            parameters in proptest::collection::vec(mail_parameter(), 0..4),
+        ) {
+            round_trip(MailCommand::new(sender, parameters))?;
+        }
+
+        #[test]
+        fn test_round_trip_rcpt(
+            recipient in email_address(),
+            parameters in proptest::collection::vec(rcpt_parameter(), 0..4),
+        ) {
+            round_trip(RcptCommand::new(recipient, parameters))?;
+        }
+
+        #[test]
+        fn test_round_trip_arguments(argument in "[ -~]{0,20}") {
+            round_trip(HelpCommand::new(Some(argument.clone())))?;
+            round_trip(VrfyCommand::new(argument.clone()))?;
+            round_trip(ExpnCommand::new(argument))?;
+        }
+
+        #[test]
+        fn test_round_trip_auth(
+            mechanism in prop_oneof![Just(Mechanism::Plain), Just(Mechanism::Xoauth2)],
+            username in "[a-z0-9@.]{1,16}",
+            password in "[a-zA-Z0-9]{1,16}",
+        ) {
+            let credentials = Credentials::new(username, password);
+            round_trip(AuthCommand::new(mechanism, credentials, None).unwrap())?;
+        }
+    }
+
+    #[test]
+    fn test_round_trip_simple() {
+        round_trip(StarttlsCommand).unwrap();
+        round_trip(DataCommand).unwrap();
+        round_trip(QuitCommand).unwrap();
+        round_trip(NoopCommand).unwrap();
+        round_trip(RsetCommand).unwrap();
+        round_trip(HelpCommand::new(None)).unwrap();
+    }
+
+    #[test]
+    fn test_client_attributes() {
+        let command = XclientCommand::new(vec![
+            ClientAttribute::Addr("2001:db8::1".parse().unwrap()),
+            ClientAttribute::Port(41234),
+            ClientAttribute::Helo("[client example]".to_string()),
+            ClientAttribute::Login("bjørn".to_string()),
+            ClientAttribute::DestAddr("192.0.2.1".parse().unwrap()),
+        ]);
+        assert_eq!(
+            command.to_string(),
+            "XCLIENT ADDR=IPV6:2001:db8::1 PORT=41234 HELO=[client+20example] \
+             LOGIN=bj+C3+B8rn DESTADDR=192.0.2.1\r\n"
+        );
+        assert_eq!(
+            command.to_string().parse::<XclientCommand>().unwrap(),
+            command
+        );
+        assert_eq!(
+            XforwardCommand::new(vec![
+                ClientAttribute::Name("client.example.org".to_string()),
+                ClientAttribute::Proto("ESMTP".to_string()),
+            ])
+            .to_string(),
+            "XFORWARD NAME=client.example.org PROTO=ESMTP\r\n"
+        );
+        assert!("XCLIENT".parse::<XclientCommand>().is_err());
+        assert!(matches!(
+            "XCLIENT FOO=bar".parse::<XclientCommand>(),
+            Err(Error::CommandParsing("Unknown attribute"))
+        ));
+        assert!("XFORWARD PORT=x".parse::<XforwardCommand>().is_err());
+    }
+
+    #[test]
+    fn test_queue_run() {
+        for (command, node) in [
+            (
+                "ETRN example.org\r\n",
+                EtrnNode::Domain("example.org".to_string()),
+            ),
+            (
+                "ETRN @example.org\r\n",
+                EtrnNode::Subdomains("example.org".to_string()),
+            ),
+            ("ETRN #backup\r\n", EtrnNode::Queue("backup".to_string())),
+        ] {
+            let etrn = EtrnCommand::new(node.clone());
+            assert_eq!(etrn.to_string(), command);
+            assert_eq!(command.parse::<EtrnCommand>().unwrap().node(), &node);
+        }
+        assert!("ETRN".parse::<EtrnCommand>().is_err());
+        assert!("ETRN @".parse::<EtrnCommand>().is_err());
+
+        assert_eq!(AtrnCommand::new(vec![]).to_string(), "ATRN\r\n");
+        let atrn = AtrnCommand::new(vec!["example.org".to_string(), "example.net".to_string()]);
+        assert_eq!(atrn.to_string(), "ATRN example.org,example.net\r\n");
+        assert_eq!(atrn.to_string().parse::<AtrnCommand>().unwrap(), atrn);
+        assert!("ATRN example.org,".parse::<AtrnCommand>().is_err());
+    }
 }
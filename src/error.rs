@@ -23,6 +23,9 @@ pub enum Error {
     /// Error parsing a response
     #[error("{0}")]
     ResponseParsing(&'static str),
+    /// Error parsing a command, with the reason
+    #[error("command parsing: {0}")]
+    CommandParsing(&'static str),
     /// Error parsing a base64 string in response
     #[error("challenge parsing: {0}")]
     ChallengeParsing(#[from] DecodeError),
@@ -49,6 +52,63 @@ pub enum Error {
     /// Timeout error
     #[error("timeout: {0}")]
     Timeout(#[from] async_std::future::TimeoutError),
+    /// The message needs SMTPUTF8, but it is not available
+    ///
+    /// [RFC 6531, section 3.2](https://tools.ietf.org/html/rfc6531#section-3.2)
+    #[error("message requires SMTPUTF8, which is not available")]
+    SmtpUtf8Required,
+    /// The message requires REQUIRETLS, but the session is not TLS-protected or the
+    /// server does not support it
+    ///
+    /// [RFC 8689, section 4.1](https://tools.ietf.org/html/rfc8689#section-4.1)
+    #[error("message requires REQUIRETLS, which is not available")]
+    RequireTlsUnavailable,
+    /// The server does not support the given `MAIL FROM` parameter or client attribute
+    #[error("server does not support {0}")]
+    UnsupportedParameter(&'static str),
+    /// The given `MAIL FROM` parameter is out of the range accepted by the server
+    #[error("invalid {0} parameter")]
+    InvalidParameter(&'static str),
+    /// Bare CR or LF in the message, on the given line
+    ///
+    /// [RFC 5321, section 2.3.8](https://tools.ietf.org/html/rfc5321#section-2.3.8)
+    #[error("bare CR or LF on message line {0}")]
+    BareLineEnding(usize),
+    /// Message line longer than allowed, on the given line
+    ///
+    /// [RFC 5321, section 4.5.3.1.6](https://tools.ietf.org/html/rfc5321#section-4.5.3.1.6)
+    #[error("message line {0} is too long")]
+    LineTooLong(usize),
+    /// Malformed xtext
+    ///
+    /// [RFC 3461, section 4](https://tools.ietf.org/html/rfc3461#section-4)
+    #[error("invalid xtext: {0}")]
+    InvalidXText(&'static str),
+    /// Response line longer than the given limit
+    #[error("response line longer than {0} bytes")]
+    ResponseLineTooLong(usize),
+    /// Response larger than the given limit
+    #[error("response larger than {0} bytes")]
+    ResponseTooLarge(usize),
+    /// The session reached the `MAILMAX` limit of the server
+    ///
+    /// The transport does not reconnect by itself, the message has to be sent over a new
+    /// connection.
+    ///
+    /// [RFC 9422, section 4.1](https://tools.ietf.org/html/rfc9422#section-4.1)
+    #[error("maximum number of transactions in the session reached")]
+    MailMaxReached,
+    /// CR or LF inside a raw command line
+    #[error("command contains CR or LF")]
+    LineBreakInCommand,
+    /// Invalid SMTP reply code
+    ///
+    /// [RFC 5321, section 4.2](https://tools.ietf.org/html/rfc5321#section-4.2)
+    #[error("invalid reply code {0}")]
+    InvalidReplyCode(u16),
+    /// The server does not support the given command
+    #[error("server does not support the {0} command")]
+    UnsupportedCommand(&'static str),
     /// Failure to parse email address.
     #[error("address parse error: {0}")]
     AddrParseError(#[from] AddrParseError),
@@ -68,7 +128,7 @@ impl From<Response> for Error {
     fn from(response: Response) -> Error {
         match response.code.severity {
             Severity::TransientNegativeCompletion => Transient(response),
-            Severity::PermanentNegativeCompletion => Permanent(response),
+            Severity::PermanentNegativeCompletion | Severity::Unknown(_) => Permanent(response),
             _ => Client("Unknown error code"),
         }
     }
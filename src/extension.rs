@@ -3,12 +3,14 @@
 use crate::authentication::Mechanism;
 use crate::error::Error;
 use crate::response::Response;
-use crate::util::XText;
+use crate::util::{StrictXText, XText};
 use hostname;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::result::Result;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Default client id.
 ///
@@ -17,6 +19,25 @@ use std::result::Result;
 /// Postfix check, but not `reject_unknown_helo_hostname`.
 const DEFAULT_DOMAIN_CLIENT_ID: &str = "localhost.localdomain";
 
+/// Largest absolute value of the `BY` deadline, which has at most 9 digits.
+///
+/// [RFC 2852, section 4](https://tools.ietf.org/html/rfc2852#section-4)
+pub(crate) const MAX_DELIVER_BY: i64 = 999_999_999;
+
+/// Latest `HOLDUNTIL` date that can be written, 9999-12-31T23:59:59Z, since the epoch.
+pub(crate) const MAX_HOLD_UNTIL: Duration = Duration::from_secs(253_402_300_799);
+
+/// Returns the time left until a `HOLDUNTIL` date, unless it is not in the future or can
+/// not be written.
+pub(crate) fn hold_until_interval(date: SystemTime) -> Option<Duration> {
+    if date > UNIX_EPOCH + MAX_HOLD_UNTIL {
+        return None;
+    }
+    date.duration_since(SystemTime::now())
+        .ok()
+        .filter(|interval| !interval.is_zero())
+}
+
 /// Client identifier, the parameter to `EHLO`
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum ClientId {
@@ -52,6 +73,21 @@ impl Display for ClientId {
     }
 }
 
+impl FromStr for ClientId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ClientId, Error> {
+        match s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            Some(literal) => match literal.strip_prefix("IPv6:") {
+                Some(ipv6) => Ok(ClientId::Ipv6(ipv6.parse()?)),
+                None => Ok(ClientId::Ipv4(literal.parse()?)),
+            },
+            None if s.is_empty() => Err(Error::Client("empty client id")),
+            None => Ok(ClientId::Domain(s.to_string())),
+        }
+    }
+}
+
 impl ClientId {
     /// Creates a new `ClientId` from a fully qualified domain name
     pub fn new(domain: String) -> ClientId {
@@ -91,6 +127,38 @@ pub enum Extension {
     StartTls,
     /// AUTH mechanism
     Authentication(Mechanism),
+    /// REQUIRETLS keyword
+    ///
+    /// RFC 8689: <https://tools.ietf.org/html/rfc8689>
+    RequireTls,
+    /// MT-PRIORITY keyword
+    ///
+    /// RFC 6710: <https://tools.ietf.org/html/rfc6710>
+    MtPriority,
+    /// DELIVERBY keyword
+    ///
+    /// RFC 2852: <https://tools.ietf.org/html/rfc2852>
+    DeliverBy,
+    /// FUTURERELEASE keyword
+    ///
+    /// RFC 4865: <https://tools.ietf.org/html/rfc4865>
+    FutureRelease,
+    /// XCLIENT keyword
+    ///
+    /// Postfix: <https://www.postfix.org/XCLIENT_README.html>
+    Xclient,
+    /// XFORWARD keyword
+    ///
+    /// Postfix: <https://www.postfix.org/XFORWARD_README.html>
+    Xforward,
+    /// ETRN keyword
+    ///
+    /// RFC 1985: <https://tools.ietf.org/html/rfc1985>
+    Etrn,
+    /// ATRN keyword
+    ///
+    /// RFC 2645: <https://tools.ietf.org/html/rfc2645>
+    Atrn,
 }
 
 impl Display for Extension {
@@ -101,10 +169,42 @@ impl Display for Extension {
             Extension::SmtpUtfEight => write!(f, "SMTPUTF8"),
             Extension::StartTls => write!(f, "STARTTLS"),
             Extension::Authentication(ref mechanism) => write!(f, "AUTH {mechanism}"),
+            Extension::RequireTls => write!(f, "REQUIRETLS"),
+            Extension::MtPriority => write!(f, "MT-PRIORITY"),
+            Extension::DeliverBy => write!(f, "DELIVERBY"),
+            Extension::FutureRelease => write!(f, "FUTURERELEASE"),
+            Extension::Xclient => write!(f, "XCLIENT"),
+            Extension::Xforward => write!(f, "XFORWARD"),
+            Extension::Etrn => write!(f, "ETRN"),
+            Extension::Atrn => write!(f, "ATRN"),
         }
     }
 }
 
+/// Limits advertised by the server with the `LIMITS` keyword
+///
+/// RFC 9422: <https://tools.ietf.org/html/rfc9422>
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct Limits {
+    /// Maximum number of transactions in a session, `MAILMAX`
+    pub mail_max: Option<usize>,
+    /// Maximum number of recipients in a transaction, `RCPTMAX`
+    pub rcpt_max: Option<usize>,
+    /// Maximum number of recipient domains in a transaction, `RCPTDOMAINMAX`
+    pub rcpt_domain_max: Option<usize>,
+}
+
+/// Hold limits advertised by the server with the `FUTURERELEASE` keyword
+///
+/// RFC 4865: <https://tools.ietf.org/html/rfc4865>
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct FutureRelease {
+    /// Maximum hold interval
+    pub max_interval: Duration,
+    /// Latest release date, if the server gave one in UTC
+    pub max_date: Option<SystemTime>,
+}
+
 /// Contains information about an SMTP server
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ServerInfo {
@@ -116,6 +216,11 @@ pub struct ServerInfo {
     ///
     /// It contains the features supported by the server and known by the `Extension` module.
     pub features: HashSet<Extension>,
+    /// All the EHLO keywords, in uppercase, with their parameters
+    ///
+    /// It contains every line of the EHLO response after the first one, including the
+    /// extensions unknown to this crate.
+    pub capabilities: HashMap<String, Vec<String>>,
 }
 
 impl Display for ServerInfo {
@@ -141,30 +246,62 @@ impl ServerInfo {
             None => return Err(Error::ResponseParsing("Could not read server name")),
         };
 
-        let mut features: HashSet<Extension> = HashSet::new();
-
-        for line in response.message.as_slice() {
-            if line.is_empty() {
-                continue;
+        let mut capabilities: HashMap<String, Vec<String>> = HashMap::new();
+        for line in response.message.iter().skip(1) {
+            let mut split = line.split_whitespace();
+            if let Some(keyword) = split.next() {
+                // Some servers split a keyword over several lines, e.g. `AUTH PLAIN` and
+                // `AUTH LOGIN XOAUTH2`.
+                capabilities
+                    .entry(keyword.to_ascii_uppercase())
+                    .or_default()
+                    .extend(split.map(ToString::to_string));
             }
+        }
 
-            let split: Vec<&str> = line.split_whitespace().collect();
-            match split.first().copied() {
-                Some("PIPELINING") => {
+        let mut features: HashSet<Extension> = HashSet::new();
+
+        for (keyword, params) in &capabilities {
+            match keyword.as_str() {
+                "PIPELINING" => {
                     features.insert(Extension::Pipelining);
                 }
-                Some("8BITMIME") => {
+                "8BITMIME" => {
                     features.insert(Extension::EightBitMime);
                 }
-                Some("SMTPUTF8") => {
+                "SMTPUTF8" => {
                     features.insert(Extension::SmtpUtfEight);
                 }
-                Some("STARTTLS") => {
+                "STARTTLS" => {
                     features.insert(Extension::StartTls);
                 }
-                Some("AUTH") => {
-                    for &mechanism in &split[1..] {
-                        match mechanism {
+                "REQUIRETLS" => {
+                    features.insert(Extension::RequireTls);
+                }
+                "MT-PRIORITY" => {
+                    features.insert(Extension::MtPriority);
+                }
+                "DELIVERBY" => {
+                    features.insert(Extension::DeliverBy);
+                }
+                "FUTURERELEASE" => {
+                    features.insert(Extension::FutureRelease);
+                }
+                "XCLIENT" => {
+                    features.insert(Extension::Xclient);
+                }
+                "XFORWARD" => {
+                    features.insert(Extension::Xforward);
+                }
+                "ETRN" => {
+                    features.insert(Extension::Etrn);
+                }
+                "ATRN" => {
+                    features.insert(Extension::Atrn);
+                }
+                "AUTH" => {
+                    for mechanism in params {
+                        match mechanism.to_ascii_uppercase().as_str() {
                             "PLAIN" => {
                                 features.insert(Extension::Authentication(Mechanism::Plain));
                             }
@@ -185,9 +322,116 @@ impl ServerInfo {
         Ok(ServerInfo {
             name: name.to_string(),
             features,
+            capabilities,
+        })
+    }
+
+    /// Parses a HELO response, from a server without ESMTP extensions
+    pub fn from_helo_response(response: &Response) -> Result<ServerInfo, Error> {
+        match response.first_word() {
+            Some(name) => Ok(ServerInfo {
+                name: name.to_string(),
+                features: HashSet::new(),
+                capabilities: HashMap::new(),
+            }),
+            None => Err(Error::ResponseParsing("Could not read server name")),
+        }
+    }
+
+    /// Returns the parameters of an EHLO keyword, if the server advertised it
+    ///
+    /// The keyword is case-insensitive.
+    pub fn capability(&self, keyword: &str) -> Option<&[String]> {
+        self.capabilities
+            .get(&keyword.to_ascii_uppercase())
+            .map(Vec::as_slice)
+    }
+
+    /// Checks if the server advertised an EHLO keyword
+    ///
+    /// The keyword is case-insensitive.
+    pub fn has_capability(&self, keyword: &str) -> bool {
+        self.capability(keyword).is_some()
+    }
+
+    /// Returns the maximum message size, if the server advertised a fixed one with `SIZE`
+    ///
+    /// [RFC 1870](https://tools.ietf.org/html/rfc1870)
+    pub fn max_size(&self) -> Option<usize> {
+        self.capability("SIZE")?
+            .first()?
+            .parse()
+            .ok()
+            .filter(|size| *size > 0)
+    }
+
+    /// Returns the priority assignment policy advertised with `MT-PRIORITY`, if any
+    pub fn mt_priority_policy(&self) -> Option<&str> {
+        self.capability("MT-PRIORITY")?.first().map(String::as_str)
+    }
+
+    /// Returns the minimum `BY` time advertised with `DELIVERBY`, 0 if there is none
+    ///
+    /// It is `None` if the server does not support `DELIVERBY`.
+    pub fn deliver_by_min_time(&self) -> Option<u64> {
+        let params = self.capability("DELIVERBY")?;
+        Some(
+            params
+                .first()
+                .and_then(|time| time.parse().ok())
+                .unwrap_or(0),
+        )
+    }
+
+    /// Returns the hold limits advertised with `FUTURERELEASE`
+    ///
+    /// It is `None` if the server does not support `FUTURERELEASE`, or if the maximum
+    /// interval is malformed.
+    pub fn future_release(&self) -> Option<FutureRelease> {
+        let params = self.capability("FUTURERELEASE")?;
+        Some(FutureRelease {
+            max_interval: Duration::from_secs(params.first()?.parse().ok()?),
+            max_date: params
+                .get(1)
+                .and_then(|date| humantime::parse_rfc3339(date).ok()),
         })
     }
 
+    /// Returns the attribute names advertised with `XCLIENT`
+    pub fn xclient_attributes(&self) -> &[String] {
+        self.capability("XCLIENT").unwrap_or_default()
+    }
+
+    /// Returns the attribute names advertised with `XFORWARD`
+    pub fn xforward_attributes(&self) -> &[String] {
+        self.capability("XFORWARD").unwrap_or_default()
+    }
+
+    /// Returns the limits advertised with `LIMITS`
+    ///
+    /// Unknown, malformed and zero limits are ignored.
+    pub fn limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        for param in self.capability("LIMITS").unwrap_or_default() {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name, value.parse().ok().filter(|value| *value > 0)),
+                None => continue,
+            };
+            match name.to_ascii_uppercase().as_str() {
+                "MAILMAX" => limits.mail_max = value,
+                "RCPTMAX" => limits.rcpt_max = value,
+                "RCPTDOMAINMAX" => limits.rcpt_domain_max = value,
+                _ => (),
+            }
+        }
+        limits
+    }
+
+    /// Returns the SASL mechanisms advertised with `AUTH`, including unknown ones
+    pub fn auth_mechanisms(&self) -> &[String] {
+        self.capability("AUTH").unwrap_or_default()
+    }
+
     /// Checks if the server supports an ESMTP feature
     pub fn supports_feature(&self, keyword: Extension) -> bool {
         self.features.contains(&keyword)
@@ -209,7 +453,35 @@ pub enum MailParameter {
     Size(usize),
     /// `SMTPUTF8` parameter
     SmtpUtfEight,
+    /// `REQUIRETLS` parameter
+    RequireTls,
+    /// `MT-PRIORITY` parameter, from -9 to 9
+    ///
+    /// [RFC 6710](https://tools.ietf.org/html/rfc6710)
+    MtPriority(i8),
+    /// `HOLDFOR` parameter of the FUTURERELEASE extension, in seconds
+    ///
+    /// [RFC 4865](https://tools.ietf.org/html/rfc4865)
+    HoldFor(u64),
+    /// `HOLDUNTIL` parameter of the FUTURERELEASE extension
+    ///
+    /// It is sent in UTC, with a precision of one second. The date must be in the future
+    /// and before year 10000, dates outside of 1970 to 9999 are clamped when formatted.
+    HoldUntil(SystemTime),
+    /// `BY` parameter of the DELIVERBY extension
+    ///
+    /// [RFC 2852](https://tools.ietf.org/html/rfc2852)
+    DeliverBy {
+        /// Number of seconds from the start of the transaction, with at most 9 digits
+        seconds: i64,
+        /// Action if the message can not be delivered in time
+        mode: DeliverByMode,
+        /// Whether to request delivery status notifications along the way
+        trace: bool,
+    },
     /// Custom parameter
+    ///
+    /// The value is encoded as xtext, with the strict encoding for the DSN `ENVID`.
     Other {
         /// Parameter keyword
         keyword: String,
@@ -224,6 +496,24 @@ impl Display for MailParameter {
             MailParameter::Body(ref value) => write!(f, "BODY={value}"),
             MailParameter::Size(size) => write!(f, "SIZE={size}"),
             MailParameter::SmtpUtfEight => f.write_str("SMTPUTF8"),
+            MailParameter::RequireTls => f.write_str("REQUIRETLS"),
+            MailParameter::MtPriority(priority) => write!(f, "MT-PRIORITY={priority}"),
+            MailParameter::HoldFor(seconds) => write!(f, "HOLDFOR={seconds}"),
+            MailParameter::HoldUntil(date) => {
+                let date = date.clamp(UNIX_EPOCH, UNIX_EPOCH + MAX_HOLD_UNTIL);
+                write!(f, "HOLDUNTIL={}", humantime::format_rfc3339_seconds(date))
+            }
+            MailParameter::DeliverBy {
+                seconds,
+                mode,
+                trace,
+            } => write!(f, "BY={};{}{}", seconds, mode, if trace { "T" } else { "" }),
+            MailParameter::Other {
+                ref keyword,
+                value: Some(ref value),
+            } if keyword.eq_ignore_ascii_case("ENVID") => {
+                write!(f, "{}={}", keyword, StrictXText(value))
+            }
             MailParameter::Other {
                 ref keyword,
                 value: Some(ref value),
@@ -254,10 +544,30 @@ impl Display for MailBodyParameter {
     }
 }
 
+/// Action of the DELIVERBY extension if the message can not be delivered in time
+#[derive(PartialEq, Eq, Clone, Debug, Copy)]
+pub enum DeliverByMode {
+    /// `R`, return the message as undeliverable
+    Return,
+    /// `N`, notify the sender and continue the delivery
+    Notify,
+}
+
+impl Display for DeliverByMode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            DeliverByMode::Return => f.write_str("R"),
+            DeliverByMode::Notify => f.write_str("N"),
+        }
+    }
+}
+
 /// A `RCPT TO` extension parameter
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum RcptParameter {
     /// Custom parameter
+    ///
+    /// The value is encoded as xtext, with the strict encoding for the DSN `ORCPT`.
     Other {
         /// Parameter keyword
         keyword: String,
@@ -269,6 +579,12 @@ pub enum RcptParameter {
 impl Display for RcptParameter {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
+            RcptParameter::Other {
+                ref keyword,
+                value: Some(ref value),
+            } if keyword.eq_ignore_ascii_case("ORCPT") => {
+                write!(f, "{}={}", keyword, StrictXText(value))
+            }
             RcptParameter::Other {
                 ref keyword,
                 value: Some(ref value),
@@ -284,10 +600,25 @@ impl Display for RcptParameter {
 #[cfg(test)]
 mod test {
 
-    use super::{ClientId, Extension, ServerInfo};
+    use super::{
+        ClientId, Extension, FutureRelease, Limits, MailParameter, RcptParameter, ServerInfo,
+    };
     use crate::authentication::Mechanism;
     use crate::response::{Category, Code, Detail, Response, Severity};
-    use std::collections::HashSet;
+    use std::collections::{HashMap, HashSet};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Builds an EHLO response advertising a single extension line.
+    fn ehlo_response(line: &str) -> Response {
+        Response::new(
+            Code::new(
+                Severity::PositiveCompletion,
+                Category::MailSystem,
+                Detail::Zero,
+            ),
+            vec!["mx.example.org".to_string(), line.to_string()],
+        )
+    }
 
     #[test]
     fn test_clientid_fmt() {
@@ -297,6 +628,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_clientid_from_str() {
+        assert_eq!(
+            "test".parse::<ClientId>().unwrap(),
+            ClientId::new("test".to_string())
+        );
+        assert_eq!(
+            "[127.0.0.1]".parse::<ClientId>().unwrap(),
+            ClientId::Ipv4(std::net::Ipv4Addr::new(127, 0, 0, 1))
+        );
+        assert_eq!(
+            "[IPv6:::1]".parse::<ClientId>().unwrap(),
+            ClientId::Ipv6(std::net::Ipv6Addr::LOCALHOST)
+        );
+        assert!("[localhost]".parse::<ClientId>().is_err());
+        assert!("".parse::<ClientId>().is_err());
+    }
+
     #[test]
     fn test_extension_fmt() {
         assert_eq!(
@@ -324,6 +673,7 @@ mod test {
                 ServerInfo {
                     name: "name".to_string(),
                     features: eightbitmime.clone(),
+                    capabilities: HashMap::new(),
                 }
             ),
             "name with {EightBitMime}".to_string()
@@ -337,6 +687,7 @@ mod test {
                 ServerInfo {
                     name: "name".to_string(),
                     features: empty,
+                    capabilities: HashMap::new(),
                 }
             ),
             "name with no supported features".to_string()
@@ -351,12 +702,25 @@ mod test {
                 ServerInfo {
                     name: "name".to_string(),
                     features: plain.clone(),
+                    capabilities: HashMap::new(),
                 }
             ),
             "name with {Authentication(Plain)}".to_string()
         );
     }
 
+    fn capabilities(lines: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        lines
+            .iter()
+            .map(|(keyword, params)| {
+                (
+                    keyword.to_string(),
+                    params.iter().map(ToString::to_string).collect(),
+                )
+            })
+            .collect()
+    }
+
     #[test]
     fn test_serverinfo() {
         let response = Response::new(
@@ -378,6 +742,7 @@ mod test {
         let server_info = ServerInfo {
             name: "me".to_string(),
             features,
+            capabilities: capabilities(&[("8BITMIME", &[]), ("SIZE", &["42"])]),
         };
 
         assert_eq!(ServerInfo::from_response(&response).unwrap(), server_info);
@@ -407,6 +772,11 @@ mod test {
         let server_info2 = ServerInfo {
             name: "me".to_string(),
             features: features2,
+            capabilities: capabilities(&[
+                ("AUTH", &["PLAIN", "CRAM-MD5", "XOAUTH2", "OTHER"]),
+                ("8BITMIME", &[]),
+                ("SIZE", &["42"]),
+            ]),
         };
 
         assert_eq!(ServerInfo::from_response(&response2).unwrap(), server_info2);
@@ -414,5 +784,153 @@ mod test {
         assert!(server_info2.supports_feature(Extension::EightBitMime));
         assert!(server_info2.supports_auth_mechanism(Mechanism::Plain));
         assert!(!server_info2.supports_feature(Extension::StartTls));
+        assert_eq!(
+            server_info2.auth_mechanisms(),
+            ["PLAIN", "CRAM-MD5", "XOAUTH2", "OTHER"]
+        );
+        assert_eq!(server_info2.max_size(), Some(42));
+    }
+
+    #[test]
+    fn test_serverinfo_repeated_keyword() {
+        let response = Response::new(
+            Code::new(
+                Severity::PositiveCompletion,
+                Category::MailSystem,
+                Detail::Zero,
+            ),
+            vec![
+                "mx.example.org".to_string(),
+                "AUTH PLAIN".to_string(),
+                "AUTH LOGIN XOAUTH2".to_string(),
+            ],
+        );
+        let server_info = ServerInfo::from_response(&response).unwrap();
+
+        assert_eq!(
+            server_info.capability("AUTH").unwrap(),
+            ["PLAIN", "LOGIN", "XOAUTH2"]
+        );
+        assert!(server_info.supports_auth_mechanism(Mechanism::Plain));
+        assert!(server_info.supports_auth_mechanism(Mechanism::Login));
+        assert!(server_info.supports_feature(Extension::Authentication(Mechanism::Xoauth2)));
+    }
+
+    #[test]
+    fn test_serverinfo_capabilities() {
+        let response = Response::new(
+            Code::new(
+                Severity::PositiveCompletion,
+                Category::MailSystem,
+                Detail::Zero,
+            ),
+            vec![
+                "mx.example.org Hello".to_string(),
+                "xclient NAME ADDR PROTO".to_string(),
+                "XFORWARD NAME ADDR".to_string(),
+                "DSN".to_string(),
+                "ETRN".to_string(),
+                "MT-PRIORITY MIXER".to_string(),
+                "SIZE 0".to_string(),
+                "auth login".to_string(),
+            ],
+        );
+        let server_info = ServerInfo::from_response(&response).unwrap();
+
+        assert_eq!(
+            server_info.capability("XCLIENT").unwrap(),
+            ["NAME", "ADDR", "PROTO"]
+        );
+        assert!(server_info.capability("dsn").unwrap().is_empty());
+        assert!(server_info.supports_feature(Extension::Xclient));
+        assert_eq!(server_info.xclient_attributes(), ["NAME", "ADDR", "PROTO"]);
+        assert_eq!(server_info.xforward_attributes(), ["NAME", "ADDR"]);
+        assert!(server_info.has_capability("MT-PRIORITY"));
+        assert!(server_info.supports_feature(Extension::Etrn));
+        assert!(!server_info.supports_feature(Extension::Atrn));
+        assert!(!server_info.has_capability("MX.EXAMPLE.ORG"));
+        assert!(!server_info.has_capability("PIPELINING"));
+        assert_eq!(server_info.max_size(), None);
+        assert!(server_info.supports_auth_mechanism(Mechanism::Login));
+        assert_eq!(server_info.limits(), Limits::default());
+        assert!(server_info.supports_feature(Extension::MtPriority));
+        assert_eq!(server_info.mt_priority_policy(), Some("MIXER"));
+        assert_eq!(server_info.deliver_by_min_time(), None);
+    }
+
+    #[test]
+    fn test_serverinfo_deliver_by() {
+        let server_info = ServerInfo::from_response(&ehlo_response("DELIVERBY 240")).unwrap();
+        assert!(server_info.supports_feature(Extension::DeliverBy));
+        assert_eq!(server_info.deliver_by_min_time(), Some(240));
+        let server_info = ServerInfo::from_response(&ehlo_response("DELIVERBY")).unwrap();
+        assert_eq!(server_info.deliver_by_min_time(), Some(0));
+    }
+
+    #[test]
+    fn test_serverinfo_future_release() {
+        let server_info =
+            ServerInfo::from_response(&ehlo_response("FUTURERELEASE 604800 2030-12-01T23:59:59Z"))
+                .unwrap();
+        assert!(server_info.supports_feature(Extension::FutureRelease));
+        assert_eq!(
+            server_info.future_release(),
+            Some(FutureRelease {
+                max_interval: Duration::from_secs(604800),
+                max_date: Some(UNIX_EPOCH + Duration::from_secs(1_922_399_999)),
+            })
+        );
+        let server_info = ServerInfo::from_response(&ehlo_response("FUTURERELEASE x")).unwrap();
+        assert_eq!(server_info.future_release(), None);
+    }
+
+    #[test]
+    fn test_hold_until_fmt() {
+        let date = UNIX_EPOCH + Duration::new(1_922_399_999, 500_000_000);
+        assert_eq!(
+            MailParameter::HoldUntil(date).to_string(),
+            "HOLDUNTIL=2030-12-01T23:59:59Z"
+        );
+        assert_eq!(
+            MailParameter::HoldUntil(UNIX_EPOCH - Duration::from_secs(1)).to_string(),
+            "HOLDUNTIL=1970-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            MailParameter::HoldUntil(UNIX_EPOCH + Duration::from_secs(1 << 40)).to_string(),
+            "HOLDUNTIL=9999-12-31T23:59:59Z"
+        );
+        assert_eq!(MailParameter::HoldFor(3600).to_string(), "HOLDFOR=3600");
+    }
+
+    #[test]
+    fn test_serverinfo_limits() {
+        let response = ehlo_response("LIMITS RCPTMAX=100 mailmax=1000 RCPTDOMAINMAX=0 FOO=1 BAR");
+        assert_eq!(
+            ServerInfo::from_response(&response).unwrap().limits(),
+            Limits {
+                mail_max: Some(1000),
+                rcpt_max: Some(100),
+                rcpt_domain_max: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_dsn_parameters_strict_xtext() {
+        let envid = MailParameter::Other {
+            keyword: "ENVID".to_string(),
+            value: Some("bjørn".to_string()),
+        };
+        assert_eq!(envid.to_string(), "ENVID=bj+C3+B8rn");
+        let orcpt = RcptParameter::Other {
+            keyword: "ORCPT".to_string(),
+            value: Some("rfc822;bjørn@example.org".to_string()),
+        };
+        assert_eq!(orcpt.to_string(), "ORCPT=rfc822;bj+C3+B8rn@example.org");
+        let other = RcptParameter::Other {
+            keyword: "X-NAME".to_string(),
+            value: Some("bjørn".to_string()),
+        };
+        assert_eq!(other.to_string(), "X-NAME=bjørn");
     }
 }
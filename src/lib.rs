@@ -11,6 +11,10 @@
 //! * STARTTLS ([RFC 2487](http://tools.ietf.org/html/rfc2487))
 //! * SMTPUTF8 ([RFC 6531](http://tools.ietf.org/html/rfc6531))
 //! * PIPELINING ([RFC 2920](<https://tools.ietf.org/html/rfc2920>))
+//!
+//! LMTP ([RFC 2033](https://tools.ietf.org/html/rfc2033)) is supported with `LmtpTransport`.
+//!
+//! The `server` module provides the server side of the protocol, mainly for testing.
 
 #![deny(
     missing_copy_implementations,
@@ -34,14 +38,23 @@ mod codec;
 pub mod commands;
 pub mod error;
 pub mod extension;
+mod lmtp_client;
+#[cfg(test)]
+mod mock;
 pub mod response;
+pub mod server;
 mod smtp_client;
 mod stream;
 mod types;
 pub mod util;
-pub use crate::smtp_client::{SmtpClient, SmtpTransport};
+pub use crate::codec::LineEndings;
+pub use crate::lmtp_client::{LmtpTransport, RecipientResult};
+pub use crate::smtp_client::{
+    AtrnOutcome, Batch, BatchError, EtrnOutcome, SmtpClient, SmtpTransport, VrfyOutcome,
+};
 pub use types::*;
 
+/// Runs an async test on the enabled runtime.
 #[cfg(test)]
 #[macro_export]
 macro_rules! async_test {
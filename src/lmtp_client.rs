@@ -0,0 +1,235 @@
+use log::debug;
+
+use crate::commands::*;
+use crate::error::{Error, SmtpResult};
+use crate::extension::{ClientId, ServerInfo};
+use crate::response::ResponseWarning;
+use crate::smtp_client::{SmtpClient, Transaction};
+use crate::stream::SmtpStream;
+use crate::{EmailAddress, SendableEmail};
+
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{BufRead, Write};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncBufRead as BufRead, AsyncWrite as Write};
+
+/// Delivery result for a single recipient
+#[derive(Debug)]
+pub struct RecipientResult {
+    /// Envelope recipient, as sent to the server
+    pub recipient: EmailAddress,
+    /// Server response
+    ///
+    /// This is the reply to `RCPT TO` if the recipient was rejected,
+    /// and the reply after the message content otherwise.
+    pub result: SmtpResult,
+}
+
+/// Structure that implements the high level LMTP client
+///
+/// LMTP is defined in [RFC 2033](https://tools.ietf.org/html/rfc2033).
+/// Unlike SMTP, the server replies for each recipient after the message is sent.
+#[derive(Debug)]
+pub struct LmtpTransport<S: BufRead + Write + Unpin> {
+    /// Information about the server
+    server_info: ServerInfo,
+    /// Information about the client
+    client_info: SmtpClient,
+    /// Low level client
+    stream: SmtpStream<S>,
+}
+
+impl<S: BufRead + Write + Unpin> LmtpTransport<S> {
+    /// Creates a new LMTP transport and connects.
+    pub async fn new(builder: SmtpClient, stream: S) -> Result<Self, Error> {
+        let mut stream = builder.stream(stream);
+        if builder.expect_greeting {
+            let _greeting = stream.read_response().await?;
+        }
+        let lhlo_response = stream
+            .lhlo(ClientId::new(builder.hello_name.to_string()))
+            .await?;
+        let server_info = ServerInfo::from_response(&lhlo_response)?;
+
+        // Print server information
+        debug!("server {}", server_info);
+
+        let transport = LmtpTransport {
+            server_info,
+            client_info: builder,
+            stream,
+        };
+        Ok(transport)
+    }
+
+    /// Returns the deviations from RFC 5321 accepted in the server responses so far
+    ///
+    /// This is always empty unless `SmtpClient::lenient_parsing` is enabled.
+    pub fn response_warnings(&self) -> &[ResponseWarning] {
+        self.stream.warnings()
+    }
+
+    /// Closes the LMTP session if possible.
+    pub async fn quit(&mut self) -> Result<(), Error> {
+        self.stream.command(QuitCommand).await?;
+
+        Ok(())
+    }
+
+    /// Sends an email.
+    ///
+    /// Returns the delivery result for each recipient of the envelope, in order.
+    /// Rejected recipients do not prevent delivery to the accepted ones.
+    pub async fn send(&mut self, email: SendableEmail) -> Result<Vec<RecipientResult>, Error> {
+        let Transaction {
+            envelope,
+            mail_options,
+            codec,
+        } = self
+            .client_info
+            .transaction(&self.server_info, &email)
+            .await?;
+
+        let mail = MailCommand::new(envelope.from().cloned(), mail_options);
+        let mut results = Vec::with_capacity(envelope.to().len());
+
+        let data_response = if self.client_info.uses_pipelining(&self.server_info) {
+            self.stream.send_command(mail).await?;
+            for to_address in envelope.to() {
+                self.stream
+                    .send_command(RcptCommand::new(to_address.clone(), vec![]))
+                    .await?;
+            }
+            self.stream.send_command(DataCommand).await?;
+
+            // Read all the replies before failing, to stay in sync with the server.
+            let mail_response = recipient_result(self.stream.read_response().await)?;
+            for to_address in envelope.to() {
+                results.push(RecipientResult {
+                    recipient: to_address.clone(),
+                    result: recipient_result(self.stream.read_response().await)?,
+                });
+            }
+            let data_response = recipient_result(self.stream.read_response().await)?;
+            mail_response?;
+            data_response
+        } else {
+            self.stream.command(mail).await?;
+            for to_address in envelope.to() {
+                let result = self
+                    .stream
+                    .command(RcptCommand::new(to_address.clone(), vec![]))
+                    .await;
+                results.push(RecipientResult {
+                    recipient: to_address.clone(),
+                    result: recipient_result(result)?,
+                });
+            }
+            if results.iter().all(|r| r.result.is_err()) {
+                // There is no recipient to send the message to.
+                self.stream.command(RsetCommand).await?;
+                return Ok(results);
+            }
+            self.stream.command(DataCommand).await
+        };
+
+        if let Err(err) = data_response {
+            // Without accepted recipients, the server rejects DATA.
+            if results.iter().all(|r| r.result.is_err()) {
+                return Ok(results);
+            }
+            return Err(err);
+        }
+
+        self.stream.send_message(email.message(), codec).await?;
+
+        // One reply per accepted recipient, in the order of RCPT commands.
+        for result in results.iter_mut().filter(|r| r.result.is_ok()) {
+            result.result = recipient_result(self.stream.read_response().await)?;
+            debug!(
+                "to=<{}> status={}",
+                result.recipient,
+                if result.result.is_ok() {
+                    "sent"
+                } else {
+                    "failed"
+                }
+            );
+        }
+
+        Ok(results)
+    }
+}
+
+/// Separates negative replies, which only concern one recipient, from errors breaking
+/// the session.
+fn recipient_result(result: SmtpResult) -> Result<SmtpResult, Error> {
+    match result {
+        Err(Error::Transient(response)) => Ok(Err(Error::Transient(response))),
+        Err(Error::Permanent(response)) => Ok(Err(Error::Permanent(response))),
+        Err(err) => Err(err),
+        Ok(response) => Ok(Ok(response)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::async_test;
+    use crate::mock::MockStream;
+    use crate::Envelope;
+
+    fn email() -> SendableEmail {
+        SendableEmail::new(
+            Envelope::new(
+                Some("user@example.org".parse().unwrap()),
+                vec![
+                    "a@example.org".parse().unwrap(),
+                    "b@example.org".parse().unwrap(),
+                    "c@example.org".parse().unwrap(),
+                ],
+            )
+            .unwrap(),
+            "Hello world",
+        )
+    }
+
+    async_test! { test_lmtp_send, {
+        for lhlo in [
+            "250-lmtp.example.org\r\n250 PIPELINING\r\n",
+            "250 lmtp.example.org\r\n",
+        ] {
+            let stream = MockStream::new(format!(
+                "220 lmtp.example.org LMTP\r\n{lhlo}250 OK\r\n250 OK\r\n550 5.1.1 Unknown\r\n\
+                 250 OK\r\n354 Go ahead\r\n250 2.0.0 Saved\r\n452 4.2.2 Mailbox full\r\n"
+            ));
+            let output = stream.output();
+            let mut transport = LmtpTransport::new(SmtpClient::new(), stream).await.unwrap();
+            let results = transport.send(email()).await.unwrap();
+
+            assert_eq!(results.len(), 3);
+            assert_eq!(results[0].recipient.to_string(), "a@example.org");
+            assert!(results[0].result.as_ref().unwrap().has_code(250));
+            assert!(matches!(&results[1].result, Err(Error::Permanent(r)) if r.has_code(550)));
+            assert!(matches!(&results[2].result, Err(Error::Transient(r)) if r.has_code(452)));
+
+            let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+            assert!(output.starts_with("LHLO [127.0.0.1]\r\n"));
+            assert!(output.ends_with("DATA\r\nHello world\r\n.\r\n"));
+        }
+    }}
+
+    async_test! { test_lmtp_send_no_recipient, {
+        let stream = MockStream::new(
+            "220 lmtp.example.org LMTP\r\n250 lmtp.example.org\r\n250 OK\r\n\
+             550 Unknown\r\n550 Unknown\r\n550 Unknown\r\n250 OK\r\n",
+        );
+        let output = stream.output();
+        let mut transport = LmtpTransport::new(SmtpClient::new(), stream).await.unwrap();
+        let results = transport.send(email()).await.unwrap();
+
+        assert!(results.iter().all(|r| r.result.is_err()));
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("RSET\r\n"));
+    }}
+}
@@ -0,0 +1,179 @@
+//! In-memory stream for tests
+
+use std::io::{BufRead as _, Cursor, Read as _};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::io;
+
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{BufRead, Read, Write};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncBufRead as BufRead, AsyncRead as Read, AsyncWrite as Write};
+
+/// Stream replaying scripted server replies and recording everything written by the client.
+#[derive(Debug)]
+pub(crate) struct MockStream {
+    input: Cursor<Vec<u8>>,
+    output: Arc<Mutex<Vec<u8>>>,
+    /// Whether to keep the written data in `output`.
+    record: bool,
+    /// Number of bytes written.
+    written: usize,
+    /// Largest single write.
+    max_write: usize,
+}
+
+impl MockStream {
+    /// Creates a stream which will return `input` to the reader.
+    pub(crate) fn new(input: impl Into<Vec<u8>>) -> Self {
+        Self {
+            input: Cursor::new(input.into()),
+            output: Default::default(),
+            record: true,
+            written: 0,
+            max_write: 0,
+        }
+    }
+
+    /// Creates a stream which only counts the written data instead of recording it.
+    pub(crate) fn sink(input: impl Into<Vec<u8>>) -> Self {
+        Self {
+            record: false,
+            ..Self::new(input)
+        }
+    }
+
+    /// Returns a handle to the data written to the stream.
+    pub(crate) fn output(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.output.clone()
+    }
+
+    /// Returns the number of bytes written to the stream.
+    pub(crate) fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Returns the size of the largest single write.
+    pub(crate) fn max_write(&self) -> usize {
+        self.max_write
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl Read for MockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let read = self.get_mut().input.read(buf.initialize_unfilled())?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "runtime-async-std")]
+impl Read for MockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().input.read(buf))
+    }
+}
+
+impl BufRead for MockStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        Poll::Ready(self.get_mut().input.fill_buf())
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().input.consume(amt)
+    }
+}
+
+impl Write for MockStream {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.record {
+            this.output.lock().unwrap().extend_from_slice(buf);
+        }
+        this.written += buf.len();
+        this.max_write = this.max_write.max(buf.len());
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reader producing `count` repetitions of `line`.
+#[derive(Debug)]
+pub(crate) struct RepeatReader {
+    line: &'static [u8],
+    pos: usize,
+    count: usize,
+}
+
+impl RepeatReader {
+    pub(crate) fn new(line: &'static [u8], count: usize) -> Self {
+        Self {
+            line,
+            pos: 0,
+            count,
+        }
+    }
+
+    fn read_to(&mut self, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        while read < buf.len() && self.count > 0 {
+            let n = (self.line.len() - self.pos).min(buf.len() - read);
+            buf[read..read + n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+            read += n;
+            self.pos += n;
+            if self.pos == self.line.len() {
+                self.pos = 0;
+                self.count -= 1;
+            }
+        }
+        read
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl Read for RepeatReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let read = self.get_mut().read_to(buf.initialize_unfilled());
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "runtime-async-std")]
+impl Read for RepeatReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(self.get_mut().read_to(buf)))
+    }
+}
@@ -21,18 +21,27 @@ use crate::error::Error;
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum Severity {
     /// 2yx
-    PositiveCompletion = 2,
+    PositiveCompletion,
     /// 3yz
-    PositiveIntermediate = 3,
+    PositiveIntermediate,
     /// 4yz
-    TransientNegativeCompletion = 4,
+    TransientNegativeCompletion,
     /// 5yz
-    PermanentNegativeCompletion = 5,
+    PermanentNegativeCompletion,
+    /// Any other first digit, only accepted by lenient parsing
+    Unknown(u8),
 }
 
 impl Display for Severity {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        write!(f, "{}", *self as u8)
+        let digit = match *self {
+            Severity::PositiveCompletion => 2,
+            Severity::PositiveIntermediate => 3,
+            Severity::TransientNegativeCompletion => 4,
+            Severity::PermanentNegativeCompletion => 5,
+            Severity::Unknown(digit) => digit,
+        };
+        write!(f, "{digit}")
     }
 }
 
@@ -130,6 +139,26 @@ pub struct Response {
     pub message: Vec<String>,
 }
 
+/// Formats the response as sent on the wire
+///
+/// Every line but the last one uses a `-` separator, as described in
+/// [RFC 5321, section 4.2.1](https://tools.ietf.org/html/rfc5321#section-4.2.1).
+/// Enhanced status codes are part of the message text, and are kept on each line.
+/// A response without message is written as a single line without text.
+impl Display for Response {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let mut lines = self.message.iter().peekable();
+        if lines.peek().is_none() {
+            return write!(f, "{} \r\n", self.code);
+        }
+        while let Some(line) = lines.next() {
+            let separator = if lines.peek().is_some() { '-' } else { ' ' };
+            write!(f, "{}{}{}\r\n", self.code, separator, line)?;
+        }
+        Ok(())
+    }
+}
+
 impl FromStr for Response {
     type Err = Error;
 
@@ -172,7 +201,7 @@ impl Response {
 
 // Parsers (originally from tokio-smtp)
 
-fn parse_code(i: &str) -> IResult<&str, Code> {
+pub(crate) fn parse_code(i: &str) -> IResult<&str, Code> {
     let (i, severity) = parse_severity(i)?;
     let (i, category) = parse_category(i)?;
     let (i, detail) = parse_detail(i)?;
@@ -258,9 +287,121 @@ pub(crate) fn parse_response(i: &str) -> IResult<&str, Response> {
     ))
 }
 
+/// Deviation from RFC 5321 accepted while parsing a response in lenient mode
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ResponseWarning {
+    /// A line ends with LF instead of CRLF
+    BareLineFeed,
+    /// A code is not followed by a space or a hyphen, like in `250\r\n`
+    MissingSeparator,
+    /// The lines of a multiline response have different codes
+    ///
+    /// The code of the last line is used for the response.
+    CodeMismatch {
+        /// Code of the first line
+        first: Code,
+        /// Differing code
+        found: Code,
+    },
+    /// The first digit of the code is not between 2 and 5
+    ///
+    /// The response keeps its code, with `Severity::Unknown`, and is handled as a permanent
+    /// negative completion.
+    UnknownSeverity(char),
+}
+
+impl Display for ResponseWarning {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match *self {
+            ResponseWarning::BareLineFeed => f.write_str("line ending without CR"),
+            ResponseWarning::MissingSeparator => f.write_str("missing separator after code"),
+            ResponseWarning::CodeMismatch { first, found } => {
+                write!(f, "code {found} differs from {first} in multiline response")
+            }
+            ResponseWarning::UnknownSeverity(digit) => {
+                write!(
+                    f,
+                    "unknown severity {digit}, handled as {}",
+                    Severity::PermanentNegativeCompletion
+                )
+            }
+        }
+    }
+}
+
+/// Parses a response, accepting the deviations described by `ResponseWarning`.
+///
+/// Returns `Ok(None)` if the response is incomplete.
+pub(crate) fn parse_response_lenient(
+    i: &str,
+) -> result::Result<Option<(Response, Vec<ResponseWarning>)>, Error> {
+    let mut warnings = vec![];
+    let mut first_code = None;
+    let mut message = vec![];
+
+    for line in i.split_inclusive('\n') {
+        let line = match line.strip_suffix("\r\n") {
+            Some(line) => line,
+            None => match line.strip_suffix('\n') {
+                Some(line) => {
+                    warnings.push(ResponseWarning::BareLineFeed);
+                    line
+                }
+                None => return Ok(None),
+            },
+        };
+
+        let code = line
+            .get(..3)
+            .ok_or(Error::ResponseParsing("Response line too short"))?;
+        let (code, severity_warning) = match code.chars().next() {
+            Some(digit @ ('0' | '1' | '6'..='9')) => {
+                let (_, code) = parse_code(&format!("5{}", &code[1..]))?;
+                (
+                    Code {
+                        severity: Severity::Unknown(digit as u8 - b'0'),
+                        ..code
+                    },
+                    Some(ResponseWarning::UnknownSeverity(digit)),
+                )
+            }
+            _ => (parse_code(code)?.1, None),
+        };
+        warnings.extend(severity_warning);
+        match first_code {
+            None => first_code = Some(code),
+            Some(first) if first != code => {
+                warnings.push(ResponseWarning::CodeMismatch { first, found: code })
+            }
+            Some(_) => {}
+        }
+
+        let (last, text) = match line[3..].chars().next() {
+            Some('-') => (false, &line[4..]),
+            Some(' ') => (true, &line[4..]),
+            None => {
+                warnings.push(ResponseWarning::MissingSeparator);
+                (true, "")
+            }
+            Some(_) => return Err(Error::ResponseParsing("Invalid separator after code")),
+        };
+        message.push(text.to_string());
+
+        if last {
+            return Ok(Some((Response::new(code, message), warnings)));
+        }
+    }
+
+    Ok(None)
+}
+
 #[cfg(test)]
 mod test {
-    use super::{parse_response, Category, Code, Detail, Response, Severity};
+    use super::{
+        parse_response, parse_response_lenient, Category, Code, Detail, Response, ResponseWarning,
+        Severity,
+    };
+    use proptest::prelude::*;
 
     #[test]
     fn test_severity_fmt() {
@@ -563,4 +704,149 @@ mod test {
             Some("")
         );
     }
+
+    #[test]
+    fn test_response_display() {
+        let code = Code::new(
+            Severity::PositiveCompletion,
+            Category::MailSystem,
+            Detail::Zero,
+        );
+        assert_eq!(
+            Response::new(code, vec!["2.1.0 OK".to_string()]).to_string(),
+            "250 2.1.0 OK\r\n"
+        );
+        assert_eq!(
+            Response::new(
+                code,
+                vec![
+                    "me".to_string(),
+                    "8BITMIME".to_string(),
+                    "SIZE 42".to_string()
+                ]
+            )
+            .to_string(),
+            "250-me\r\n250-8BITMIME\r\n250 SIZE 42\r\n"
+        );
+        assert_eq!(Response::new(code, vec![]).to_string(), "250 \r\n");
+    }
+
+    fn code() -> impl Strategy<Value = Code> {
+        "[2-5][0-5][0-9]".prop_map(|code| parse_response(&format!("{code} \r\n")).unwrap().1.code)
+    }
+
+    proptest! {
+        #[test]
+        fn test_response_round_trip(
+            code in code(),
+            message in proptest::collection::vec("([2-5]\\.[0-9]{1,3}\\.[0-9]{1,3} )?[ -~]{0,40}", 1..5),
+        ) {
+            let response = Response::new(code, message);
+            let raw = response.to_string();
+            let (rest, parsed) = parse_response(&raw).unwrap();
+            prop_assert_eq!(rest, "");
+            prop_assert_eq!(parsed, response);
+        }
+
+        #[test]
+        fn test_response_parse_display(raw in "(250-[ -~]{0,20}\r\n){0,3}250 [ -~]{0,20}\r\n") {
+            let (_, response) = parse_response(&raw).unwrap();
+            prop_assert_eq!(response.to_string(), raw);
+        }
+    }
+
+    fn parse_code(code: &str) -> Code {
+        super::parse_code(code).unwrap().1
+    }
+
+    #[test]
+    fn test_response_lenient_corpus() {
+        use ResponseWarning::*;
+
+        let corpus: &[(&str, &str, &[&str], &[ResponseWarning])] = &[
+            // Strict responses are parsed the same.
+            (
+                "250-mx.example.org\r\n250 8BITMIME\r\n",
+                "250",
+                &["mx.example.org", "8BITMIME"],
+                &[],
+            ),
+            // Reply to NOOP without text.
+            ("250\r\n", "250", &[""], &[MissingSeparator]),
+            ("421\n", "421", &[""], &[BareLineFeed, MissingSeparator]),
+            // Greeting and EHLO with LF line endings.
+            (
+                "220 mail.example.com ESMTP ready\n",
+                "220",
+                &["mail.example.com ESMTP ready"],
+                &[BareLineFeed],
+            ),
+            (
+                "250-mail.example.com\n250-SIZE 10240000\r\n250 HELP\n",
+                "250",
+                &["mail.example.com", "SIZE 10240000", "HELP"],
+                &[BareLineFeed, BareLineFeed],
+            ),
+            // Multiline response ending with a bare code.
+            (
+                "250-mail.example.com\r\n250\r\n",
+                "250",
+                &["mail.example.com", ""],
+                &[MissingSeparator],
+            ),
+            // Codes changing in the middle of a multiline response.
+            (
+                "250-mail.example.com Hello\r\n250-PIPELINING\r\n251 8BITMIME\r\n",
+                "251",
+                &["mail.example.com Hello", "PIPELINING", "8BITMIME"],
+                &[CodeMismatch {
+                    first: parse_code("250"),
+                    found: parse_code("251"),
+                }],
+            ),
+            (
+                "550-5.7.1 Blocked\r\n554 5.7.1 See https://example.com\r\n",
+                "554",
+                &["5.7.1 Blocked", "5.7.1 See https://example.com"],
+                &[CodeMismatch {
+                    first: parse_code("550"),
+                    found: parse_code("554"),
+                }],
+            ),
+            // First digits outside 2 to 5.
+            (
+                "600 Unknown\r\n",
+                "600",
+                &["Unknown"],
+                &[UnknownSeverity('6')],
+            ),
+            (
+                "150 Go ahead\r\n",
+                "150",
+                &["Go ahead"],
+                &[UnknownSeverity('1')],
+            ),
+        ];
+
+        for (raw, expected_code, message, expected_warnings) in corpus {
+            let (response, warnings) = parse_response_lenient(raw).unwrap().unwrap();
+            assert_eq!(response.code.to_string(), *expected_code, "{:?}", raw);
+            assert_eq!(response.message, *message, "{:?}", raw);
+            assert_eq!(warnings, *expected_warnings, "{:?}", raw);
+            // Strict parsing rejects them.
+            if !expected_warnings.is_empty() {
+                assert!(parse_response(raw).is_err(), "{:?}", raw);
+            }
+        }
+    }
+
+    #[test]
+    fn test_response_lenient_incomplete() {
+        for raw in ["", "250", "250-mail.example.com\r\n", "250-a\n250 b"] {
+            assert_eq!(parse_response_lenient(raw).unwrap(), None, "{:?}", raw);
+        }
+        for raw in ["25\r\n", "250x\r\n", "2a0 OK\r\n"] {
+            assert!(parse_response_lenient(raw).is_err(), "{:?}", raw);
+        }
+    }
 }
@@ -0,0 +1,583 @@
+//! Embeddable SMTP server
+//!
+//! It runs the server side of an SMTP session on any stream, which makes it usable as an
+//! in-process test double for `SmtpTransport`. The decisions about the session are
+//! delegated to a [`Handler`], which can accept, reject or delay every step.
+
+use futures::future::{self, BoxFuture};
+use log::debug;
+use nom::combinator::all_consuming;
+
+use crate::commands::{
+    DataCommand, EhloCommand, HeloCommand, MailCommand, NoopCommand, QuitCommand, RcptCommand,
+    RsetCommand,
+};
+use crate::error::Error;
+use crate::extension::ClientId;
+use crate::response::{parse_code, Response};
+use crate::{EmailAddress, Envelope};
+
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{prelude::*, BufRead, Write, WriteExt};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{
+    AsyncBufRead as BufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite as Write, AsyncWriteExt,
+};
+
+/// Maximum length of a line, including a stuffed dot and CRLF
+///
+/// [RFC 5321, section 4.5.3.1.6](https://tools.ietf.org/html/rfc5321#section-4.5.3.1.6)
+const MAX_LINE_LENGTH: usize = 1001;
+
+/// Default maximum size of a message, after dot-stuffing is removed
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Creates a response with the given code and text.
+///
+/// The text is split into several lines on `\n`. Fails with `Error::InvalidReplyCode`
+/// if `code` is not a valid SMTP reply code.
+pub fn reply(code: u16, text: &str) -> Result<Response, Error> {
+    let digits = code.to_string();
+    let (_, parsed) =
+        all_consuming(parse_code)(&digits).map_err(|_| Error::InvalidReplyCode(code))?;
+    Ok(Response::new(
+        parsed,
+        text.split('\n').map(ToString::to_string).collect(),
+    ))
+}
+
+/// Creates a response sent by the server itself, whose code is known to be valid.
+fn builtin_reply(code: u16, text: &str) -> Response {
+    reply(code, text).expect("valid built-in reply code")
+}
+
+/// Decisions taken by the server during the session
+///
+/// All methods have a default implementation accepting the command.
+/// The replies can be delayed by awaiting in the returned future.
+pub trait Handler: Send {
+    /// Greeting sent when the session starts
+    fn greeting(&mut self) -> BoxFuture<'_, Response> {
+        Box::pin(future::ready(builtin_reply(220, "localhost ESMTP")))
+    }
+
+    /// Reply to `EHLO`
+    ///
+    /// Only the first line is sent in reply to `HELO`.
+    fn ehlo(&mut self, _client_id: ClientId) -> BoxFuture<'_, Response> {
+        Box::pin(future::ready(builtin_reply(
+            250,
+            "localhost\nPIPELINING\n8BITMIME\nSMTPUTF8",
+        )))
+    }
+
+    /// Reply to `MAIL FROM`
+    fn mail(&mut self, _command: MailCommand) -> BoxFuture<'_, Response> {
+        Box::pin(future::ready(builtin_reply(250, "OK")))
+    }
+
+    /// Reply to `RCPT TO`
+    fn rcpt(&mut self, _command: RcptCommand) -> BoxFuture<'_, Response> {
+        Box::pin(future::ready(builtin_reply(250, "OK")))
+    }
+
+    /// Reply to `DATA`
+    ///
+    /// The message is only read if this reply is positive.
+    fn data(&mut self) -> BoxFuture<'_, Response> {
+        Box::pin(future::ready(builtin_reply(
+            354,
+            "End data with <CR><LF>.<CR><LF>",
+        )))
+    }
+
+    /// Reply after the message content
+    ///
+    /// The envelope only contains the accepted sender and recipients, and the content has
+    /// dot-stuffing removed.
+    fn message(&mut self, _envelope: Envelope, _content: Vec<u8>) -> BoxFuture<'_, Response> {
+        Box::pin(future::ready(builtin_reply(250, "OK")))
+    }
+}
+
+/// Command sent by the client
+#[derive(Debug)]
+enum Command {
+    Ehlo(ClientId),
+    Helo(ClientId),
+    Mail(MailCommand),
+    Rcpt(RcptCommand),
+    Data,
+    Rset,
+    Noop,
+    Quit,
+    /// Known command with invalid arguments
+    Invalid,
+    /// Unknown or unsupported command
+    Unknown,
+}
+
+/// Line read from the client
+#[derive(Debug)]
+enum Line {
+    /// Line with its line ending
+    Complete(Vec<u8>),
+    /// Line longer than `MAX_LINE_LENGTH`, skipped up to its line ending
+    TooLong,
+}
+
+/// SMTP server session
+#[derive(Debug)]
+pub struct SmtpServer<S: BufRead + Write + Unpin, H: Handler> {
+    stream: S,
+    handler: H,
+    /// Sender of the current transaction, `Some(None)` for the null reverse-path
+    sender: Option<Option<EmailAddress>>,
+    /// Accepted recipients of the current transaction
+    recipients: Vec<EmailAddress>,
+    /// Maximum size of a message
+    max_message_size: usize,
+}
+
+impl<S: BufRead + Write + Unpin, H: Handler> SmtpServer<S, H> {
+    /// Creates a new server session on the given stream.
+    pub fn new(stream: S, handler: H) -> Self {
+        SmtpServer {
+            stream,
+            handler,
+            sender: None,
+            recipients: Vec::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Set the maximum size of a message, after dot-stuffing is removed
+    ///
+    /// Larger messages are rejected with a 552 reply. Defaults to 10 MiB.
+    pub fn max_message_size(self, size: usize) -> Self {
+        Self {
+            max_message_size: size,
+            ..self
+        }
+    }
+
+    /// Runs the session until the client sends `QUIT` or closes the connection.
+    ///
+    /// Returns the handler, so it can be inspected afterwards.
+    pub async fn run(mut self) -> Result<H, Error> {
+        let greeting = self.handler.greeting().await;
+        self.write_response(&greeting).await?;
+
+        while let Some(line) = self.read_line().await? {
+            let line = match line {
+                Line::Complete(line) => line,
+                Line::TooLong => {
+                    self.write_response(&builtin_reply(500, "Line too long"))
+                        .await?;
+                    continue;
+                }
+            };
+            let response = match parse_command(&line) {
+                Command::Ehlo(client_id) => {
+                    self.reset();
+                    self.handler.ehlo(client_id).await
+                }
+                Command::Helo(client_id) => {
+                    self.reset();
+                    let mut response = self.handler.ehlo(client_id).await;
+                    response.message.truncate(1);
+                    response
+                }
+                Command::Mail(command) => {
+                    if self.sender.is_some() {
+                        builtin_reply(503, "Nested MAIL command")
+                    } else {
+                        let sender = command.sender().cloned();
+                        let response = self.handler.mail(command).await;
+                        if response.is_positive() {
+                            self.sender = Some(sender);
+                        }
+                        response
+                    }
+                }
+                Command::Rcpt(command) => {
+                    if self.sender.is_none() {
+                        builtin_reply(503, "Need MAIL before RCPT")
+                    } else {
+                        let recipient = command.recipient().clone();
+                        let response = self.handler.rcpt(command).await;
+                        if response.is_positive() {
+                            self.recipients.push(recipient);
+                        }
+                        response
+                    }
+                }
+                Command::Data => match self.sender.take() {
+                    None => builtin_reply(503, "Need MAIL before DATA"),
+                    Some(_) if self.recipients.is_empty() => {
+                        self.reset();
+                        builtin_reply(554, "No valid recipients")
+                    }
+                    Some(sender) => {
+                        let recipients = std::mem::take(&mut self.recipients);
+                        let response = self.handler.data().await;
+                        if response.is_positive() {
+                            self.write_response(&response).await?;
+                            match self.read_message().await? {
+                                Ok(content) => {
+                                    let envelope =
+                                        Envelope::new(sender, recipients).map_err(|_| {
+                                            Error::Client("missing destination address")
+                                        })?;
+                                    self.handler.message(envelope, content).await
+                                }
+                                Err(response) => response,
+                            }
+                        } else {
+                            response
+                        }
+                    }
+                },
+                Command::Rset => {
+                    self.reset();
+                    builtin_reply(250, "OK")
+                }
+                Command::Noop => builtin_reply(250, "OK"),
+                Command::Quit => {
+                    self.write_response(&builtin_reply(221, "Bye")).await?;
+                    break;
+                }
+                Command::Invalid => builtin_reply(501, "Syntax error in parameters or arguments"),
+                Command::Unknown => builtin_reply(502, "Command not implemented"),
+            };
+            self.write_response(&response).await?;
+        }
+
+        Ok(self.handler)
+    }
+
+    /// Aborts the current transaction.
+    fn reset(&mut self) {
+        self.sender = None;
+        self.recipients.clear();
+    }
+
+    /// Reads a line, with its line ending.
+    ///
+    /// Returns `None` at the end of the stream.
+    async fn read_line(&mut self) -> Result<Option<Line>, Error> {
+        let mut line = Vec::new();
+        let read = (&mut self.stream)
+            .take(MAX_LINE_LENGTH as u64)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read == MAX_LINE_LENGTH && !line.ends_with(b"\n") {
+            debug!("<< line longer than {} bytes", MAX_LINE_LENGTH);
+            // Skip the rest of the line.
+            while !line.ends_with(b"\n") {
+                line.clear();
+                let read = (&mut self.stream)
+                    .take(MAX_LINE_LENGTH as u64)
+                    .read_until(b'\n', &mut line)
+                    .await?;
+                if read == 0 {
+                    break;
+                }
+            }
+            return Ok(Some(Line::TooLong));
+        }
+        debug!("<< {}", String::from_utf8_lossy(&line).trim_end());
+        Ok(Some(Line::Complete(line)))
+    }
+
+    /// Reads the message content up to the final dot, and removes dot-stuffing.
+    ///
+    /// If a line or the message is too long, the rest of the message is skipped and the
+    /// reply to send is returned instead.
+    async fn read_message(&mut self) -> Result<Result<Vec<u8>, Response>, Error> {
+        let mut content = Vec::new();
+        let mut rejection = None;
+        loop {
+            let line = self.read_line().await?.ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "incomplete message")
+            })?;
+            let line = match line {
+                Line::Complete(line) => line,
+                Line::TooLong => {
+                    rejection.get_or_insert_with(|| builtin_reply(500, "Line too long"));
+                    continue;
+                }
+            };
+            if line == b".\r\n" {
+                return Ok(rejection.map_or(Ok(content), Err));
+            }
+            if rejection.is_some() {
+                continue;
+            }
+            let line = line.strip_prefix(b".").unwrap_or(&line);
+            if content.len() + line.len() > self.max_message_size {
+                rejection = Some(builtin_reply(
+                    552,
+                    "Message size exceeds fixed maximum message size",
+                ));
+                content = Vec::new();
+                continue;
+            }
+            content.extend_from_slice(line);
+        }
+    }
+
+    async fn write_response(&mut self, response: &Response) -> Result<(), Error> {
+        let buf = response.to_string();
+        debug!(">> {}", buf.trim_end());
+        self.stream.write_all(buf.as_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Parses a command line sent by the client.
+fn parse_command(line: &[u8]) -> Command {
+    let line = match std::str::from_utf8(line) {
+        Ok(line) => line.trim_end_matches(['\r', '\n']),
+        Err(_) => return Command::Invalid,
+    };
+    let verb = line.split(' ').next().unwrap_or_default();
+    let parsed = match verb.to_ascii_uppercase().as_str() {
+        "EHLO" => line
+            .parse::<EhloCommand>()
+            .map(|command| Command::Ehlo(command.client_id().clone())),
+        "HELO" => line
+            .parse::<HeloCommand>()
+            .map(|command| Command::Helo(command.client_id().clone())),
+        "MAIL" => line.parse().map(Command::Mail),
+        "RCPT" => line.parse().map(Command::Rcpt),
+        "DATA" => line.parse::<DataCommand>().map(|_| Command::Data),
+        "RSET" => line.parse::<RsetCommand>().map(|_| Command::Rset),
+        "NOOP" => line.parse::<NoopCommand>().map(|_| Command::Noop),
+        "QUIT" => line.parse::<QuitCommand>().map(|_| Command::Quit),
+        _ => return Command::Unknown,
+    };
+    parsed.unwrap_or(Command::Invalid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::async_test;
+    use crate::extension::{MailBodyParameter, MailParameter};
+    use crate::mock::MockStream;
+
+    /// Records the messages and rejects recipients at `example.net`.
+    #[derive(Debug, Default)]
+    struct TestHandler {
+        messages: Vec<(Envelope, Vec<u8>)>,
+    }
+
+    impl Handler for TestHandler {
+        fn rcpt(&mut self, command: RcptCommand) -> BoxFuture<'_, Response> {
+            Box::pin(async move {
+                if command.recipient().domain() == Some("example.net") {
+                    reply(550, "No such user").unwrap()
+                } else {
+                    reply(250, "OK").unwrap()
+                }
+            })
+        }
+
+        fn message(&mut self, envelope: Envelope, content: Vec<u8>) -> BoxFuture<'_, Response> {
+            self.messages.push((envelope, content));
+            Box::pin(future::ready(reply(250, "Queued").unwrap()))
+        }
+    }
+
+    async fn run(input: &str) -> (TestHandler, String) {
+        let stream = MockStream::new(input);
+        let output = stream.output();
+        let handler = SmtpServer::new(stream, TestHandler::default())
+            .run()
+            .await
+            .unwrap();
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        (handler, output)
+    }
+
+    #[test]
+    fn test_reply() {
+        let response = reply(250, "first\nsecond").unwrap();
+        assert!(response.has_code(250));
+        assert_eq!(response.message, vec!["first", "second"]);
+
+        for code in [0, 99, 199, 2500, 600, 260] {
+            assert!(matches!(reply(code, "OK"), Err(Error::InvalidReplyCode(c)) if c == code));
+        }
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert!(matches!(
+            parse_command(b"EHLO [127.0.0.1]\r\n"),
+            Command::Ehlo(ClientId::Ipv4(_))
+        ));
+        assert!(matches!(
+            parse_command(b"helo [IPv6:::1]\r\n"),
+            Command::Helo(ClientId::Ipv6(_))
+        ));
+        assert!(matches!(parse_command(b"EHLO\r\n"), Command::Invalid));
+        assert!(matches!(parse_command(b"VRFY root\r\n"), Command::Unknown));
+        match parse_command(b"MAIL FROM:<user@example.org> BODY=8BITMIME SIZE=42 X=y\r\n") {
+            Command::Mail(command) => assert_eq!(
+                command,
+                MailCommand::new(
+                    Some("user@example.org".parse().unwrap()),
+                    vec![
+                        MailParameter::Body(MailBodyParameter::EightBitMime),
+                        MailParameter::Size(42),
+                        MailParameter::Other {
+                            keyword: "X".to_string(),
+                            value: Some("y".to_string())
+                        },
+                    ]
+                )
+            ),
+            command => panic!("unexpected {:?}", command),
+        }
+        match parse_command(b"mail from: <>\r\n") {
+            Command::Mail(command) => assert_eq!(command.sender(), None),
+            command => panic!("unexpected {:?}", command),
+        }
+        assert!(matches!(
+            parse_command(b"MAIL FROM:user@example.org\r\n"),
+            Command::Invalid
+        ));
+    }
+
+    async_test! { test_server_session, {
+        let (handler, output) = run(
+            "EHLO client.example.org\r\n\
+             MAIL FROM:<user@example.org>\r\n\
+             RCPT TO:<a@example.org>\r\n\
+             RCPT TO:<b@example.net>\r\n\
+             DATA\r\n\
+             Subject: test\r\n\
+             \r\n\
+             ..hidden\r\n\
+             .\r\n\
+             QUIT\r\n",
+        )
+        .await;
+
+        assert_eq!(
+            output,
+            "220 localhost ESMTP\r\n\
+             250-localhost\r\n250-PIPELINING\r\n250-8BITMIME\r\n250 SMTPUTF8\r\n\
+             250 OK\r\n\
+             250 OK\r\n\
+             550 No such user\r\n\
+             354 End data with <CR><LF>.<CR><LF>\r\n\
+             250 Queued\r\n\
+             221 Bye\r\n"
+        );
+        assert_eq!(handler.messages.len(), 1);
+        let (envelope, content) = &handler.messages[0];
+        assert_eq!(envelope.from().unwrap().to_string(), "user@example.org");
+        assert_eq!(envelope.to().len(), 1);
+        assert_eq!(content, b"Subject: test\r\n\r\n.hidden\r\n");
+    }}
+
+    async_test! { test_server_bad_sequence, {
+        let (handler, output) = run(
+            "HELO client.example.org\r\n\
+             RCPT TO:<a@example.org>\r\n\
+             MAIL FROM:<>\r\n\
+             MAIL FROM:<>\r\n\
+             RCPT TO:<b@example.net>\r\n\
+             DATA\r\n\
+             DATA\r\n\
+             FOO\r\n",
+        )
+        .await;
+
+        assert_eq!(
+            output,
+            "220 localhost ESMTP\r\n\
+             250 localhost\r\n\
+             503 Need MAIL before RCPT\r\n\
+             250 OK\r\n\
+             503 Nested MAIL command\r\n\
+             550 No such user\r\n\
+             554 No valid recipients\r\n\
+             503 Need MAIL before DATA\r\n\
+             502 Command not implemented\r\n"
+        );
+        assert!(handler.messages.is_empty());
+    }}
+
+    async_test! { test_server_limits, {
+        let long_line = "a".repeat(2000);
+        let data = "MAIL FROM:<>\r\nRCPT TO:<a@example.org>\r\nDATA\r\n";
+        let stream = MockStream::new(format!(
+            "MAIL FROM:<{long_line}@example.org>\r\n\
+             EHLO client.example.org\r\n\
+             {data}{long_line}\r\n.\r\n\
+             {data}first\r\nsecond\r\n.\r\n\
+             {data}first\r\n.\r\n"
+        ));
+        let output = stream.output();
+        let handler = SmtpServer::new(stream, TestHandler::default())
+            .max_message_size(10)
+            .run()
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let accepted = "250 OK\r\n250 OK\r\n354 End data with <CR><LF>.<CR><LF>\r\n";
+        assert_eq!(
+            output,
+            format!(
+                "220 localhost ESMTP\r\n\
+                 500 Line too long\r\n\
+                 250-localhost\r\n250-PIPELINING\r\n250-8BITMIME\r\n250 SMTPUTF8\r\n\
+                 {accepted}500 Line too long\r\n\
+                 {accepted}552 Message size exceeds fixed maximum message size\r\n\
+                 {accepted}250 Queued\r\n"
+            )
+        );
+        assert_eq!(handler.messages.len(), 1);
+        assert_eq!(handler.messages[0].1, b"first\r\n");
+    }}
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn test_server_with_transport() {
+        use crate::{SendableEmail, SmtpClient, SmtpTransport};
+        use tokio::io::BufStream;
+
+        let (client, server) = tokio::io::duplex(1024);
+        let server = SmtpServer::new(BufStream::new(server), TestHandler::default());
+        let client = async {
+            let stream = BufStream::new(client);
+            let mut transport = SmtpTransport::new(SmtpClient::new(), stream).await?;
+            let email = SendableEmail::new(
+                Envelope::new(
+                    Some("user@example.org".parse().unwrap()),
+                    vec!["root@example.org".parse().unwrap()],
+                )
+                .unwrap(),
+                ".Hello world\n",
+            );
+            let response = transport.send(email).await?;
+            transport.quit().await?;
+            Ok::<_, Error>(response)
+        };
+        let (handler, response) = tokio::join!(server.run(), client);
+
+        assert_eq!(response.unwrap().first_line(), Some("Queued"));
+        let handler = handler.unwrap();
+        assert_eq!(handler.messages.len(), 1);
+        assert_eq!(handler.messages[0].1, b".Hello world\r\n");
+    }
+}
@@ -1,32 +1,55 @@
-use std::fmt::Debug;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::time::Duration;
 
 use log::{debug, info};
 
 use crate::authentication::{Credentials, Mechanism};
+use crate::codec::{ClientCodec, LineEndings, MAX_LINE_LENGTH};
 use crate::commands::*;
 use crate::error::{Error, SmtpResult};
-use crate::extension::{ClientId, Extension, MailBodyParameter, MailParameter, ServerInfo};
-use crate::stream::SmtpStream;
-use crate::SendableEmail;
+use crate::extension::{
+    hold_until_interval, ClientId, DeliverByMode, Extension, Limits, MailBodyParameter,
+    MailParameter, ServerInfo, MAX_DELIVER_BY,
+};
+use crate::response::{Response, ResponseWarning};
+use crate::stream::{SmtpStream, MAX_RESPONSE_LINE_LENGTH, MAX_RESPONSE_SIZE};
+use crate::{EmailAddress, Envelope, Mailbox, SendableEmail};
 
 #[cfg(feature = "runtime-async-std")]
-use async_std::io::{BufRead, Write};
+use async_std::io::{self, BufRead, Write};
 #[cfg(feature = "runtime-tokio")]
-use tokio::io::{AsyncBufRead as BufRead, AsyncWrite as Write};
+use tokio::io::{self, AsyncBufRead as BufRead, AsyncWrite as Write};
 
 /// Contains client configuration
 #[derive(Debug)]
 pub struct SmtpClient {
     /// Name sent during EHLO
-    hello_name: ClientId,
-    /// Enable UTF8 mailboxes in envelope or headers
+    pub(crate) hello_name: ClientId,
+    /// Allow SMTPUTF8 for messages with UTF8 mailboxes in envelope or headers
     smtp_utf8: bool,
     /// Whether to expect greeting.
     /// Normally the server sends a greeting after connection,
     /// but not after STARTTLS.
-    expect_greeting: bool,
+    pub(crate) expect_greeting: bool,
     /// Use pipelining if the server supports it
     pipelining: bool,
+    /// Handling of bare CR and LF in messages
+    line_endings: LineEndings,
+    /// Stuff dots after bare CR and LF passed through in messages
+    smuggling_protection: bool,
+    /// Fail on message lines longer than 998 characters
+    reject_long_lines: bool,
+    /// Accept server responses deviating from RFC 5321
+    lenient_parsing: bool,
+    /// Maximum length of a response line
+    max_response_line_length: usize,
+    /// Maximum size of a response
+    max_response_size: usize,
+    /// Replace invalid UTF-8 in responses
+    lossy_responses: bool,
+    /// Whether the stream is assumed to be protected with TLS
+    assume_tls: bool,
 }
 
 impl Default for SmtpClient {
@@ -51,10 +74,21 @@ impl SmtpClient {
             hello_name: Default::default(),
             expect_greeting: true,
             pipelining: true,
+            line_endings: LineEndings::Normalize,
+            smuggling_protection: true,
+            reject_long_lines: false,
+            lenient_parsing: false,
+            max_response_line_length: MAX_RESPONSE_LINE_LENGTH,
+            max_response_size: MAX_RESPONSE_SIZE,
+            lossy_responses: false,
+            assume_tls: false,
         }
     }
 
     /// Enable SMTPUTF8 if the server supports it
+    ///
+    /// The `SMTPUTF8` parameter is only sent for messages which require it, see
+    /// `SendableEmail::requires_smtp_utf8`.
     pub fn smtp_utf8(self, enabled: bool) -> SmtpClient {
         Self {
             smtp_utf8: enabled,
@@ -70,6 +104,95 @@ impl SmtpClient {
         }
     }
 
+    /// Set the handling of bare CR and LF in messages
+    ///
+    /// By default they are replaced by CRLF. `LineEndings::Reject` checks the message like
+    /// `reject_long_lines` does.
+    pub fn line_endings(self, line_endings: LineEndings) -> SmtpClient {
+        Self {
+            line_endings,
+            ..self
+        }
+    }
+
+    /// Enable protection against SMTP smuggling
+    ///
+    /// Only relevant with `LineEndings::PassThrough`, in which case dots following bare CR
+    /// and LF are stuffed, so `\n.\n` and similar sequences are never sent. Enabled by default.
+    pub fn smuggling_protection(self, enabled: bool) -> SmtpClient {
+        Self {
+            smuggling_protection: enabled,
+            ..self
+        }
+    }
+
+    /// Fail with `Error::LineTooLong` on message lines longer than 998 characters
+    ///
+    /// Messages held in memory are checked before the transaction is started. Messages
+    /// created with `SendableEmail::new_with_reader` are checked while they are sent.
+    pub fn reject_long_lines(self, enabled: bool) -> SmtpClient {
+        Self {
+            reject_long_lines: enabled,
+            ..self
+        }
+    }
+
+    /// Accept server responses deviating from RFC 5321
+    ///
+    /// In lenient mode, codes without separator (`250\r\n`), LF line endings, differing
+    /// codes in multiline responses and first digits outside 2 to 5 are accepted. Each
+    /// deviation is logged and recorded, see `SmtpTransport::response_warnings`.
+    /// Disabled by default.
+    pub fn lenient_parsing(self, enabled: bool) -> SmtpClient {
+        Self {
+            lenient_parsing: enabled,
+            ..self
+        }
+    }
+
+    /// Set the maximum length of a server response line, including the line ending
+    ///
+    /// Longer lines fail with `Error::ResponseLineTooLong`. Defaults to 4096 bytes.
+    pub fn max_response_line_length(self, length: usize) -> SmtpClient {
+        Self {
+            max_response_line_length: length,
+            ..self
+        }
+    }
+
+    /// Set the maximum size of a server response, including all its lines
+    ///
+    /// Larger responses fail with `Error::ResponseTooLarge`. Defaults to 64 KiB.
+    pub fn max_response_size(self, size: usize) -> SmtpClient {
+        Self {
+            max_response_size: size,
+            ..self
+        }
+    }
+
+    /// Replace invalid UTF-8 in server responses with U+FFFD
+    ///
+    /// By default, such responses are an error.
+    pub fn lossy_responses(self, enabled: bool) -> SmtpClient {
+        Self {
+            lossy_responses: enabled,
+            ..self
+        }
+    }
+
+    /// Tell the transport that the stream is protected with TLS
+    ///
+    /// Use this for implicit TLS connections, and for the connection upgraded after
+    /// `SmtpTransport::starttls`. The transport can not find it out by itself and trusts
+    /// this setting: messages requiring REQUIRETLS are only sent when it is enabled, so it
+    /// must not be enabled for plain text streams. Disabled by default.
+    pub fn assume_tls(self, enabled: bool) -> SmtpClient {
+        Self {
+            assume_tls: enabled,
+            ..self
+        }
+    }
+
     /// Set the name used during EHLO
     pub fn hello_name(self, name: ClientId) -> SmtpClient {
         Self {
@@ -89,6 +212,162 @@ impl SmtpClient {
     }
 }
 
+/// Envelope, `MAIL FROM` parameters and codec used to send a message
+#[derive(Debug)]
+pub(crate) struct Transaction {
+    pub(crate) envelope: Envelope,
+    pub(crate) mail_options: Vec<MailParameter>,
+    pub(crate) codec: ClientCodec,
+}
+
+impl SmtpClient {
+    /// Prepares sending `email` to a server with the given features.
+    ///
+    /// Fails if the message can not be sent to this server.
+    pub(crate) async fn transaction(
+        &self,
+        server_info: &ServerInfo,
+        email: &SendableEmail,
+    ) -> Result<Transaction, Error> {
+        let codec = ClientCodec::new()
+            .line_endings(self.line_endings)
+            .smuggling_protection(self.smuggling_protection)
+            .max_line_length(if self.reject_long_lines {
+                Some(MAX_LINE_LENGTH)
+            } else {
+                None
+            });
+
+        // Check the message before starting the transaction if possible, so the session is
+        // still usable when it is rejected and the caller can send the fixed content again.
+        // A message from a reader can only be checked while it is sent.
+        if let Some(message) = email.message_bytes() {
+            if self.reject_long_lines || self.line_endings == LineEndings::Reject {
+                let mut check = codec;
+                check.encode(message, io::sink()).await?;
+                check.encode(&[], io::sink()).await?;
+            }
+        }
+
+        // Mail
+        let mut mail_options = vec![];
+
+        for parameter in email.mail_parameters() {
+            check_mail_parameter(server_info, parameter)?;
+            mail_options.push(parameter.clone());
+        }
+
+        if email.requires_tls() {
+            if !self.assume_tls || !server_info.supports_feature(Extension::RequireTls) {
+                return Err(Error::RequireTlsUnavailable);
+            }
+            mail_options.push(MailParameter::RequireTls);
+        }
+
+        if server_info.supports_feature(Extension::EightBitMime) {
+            mail_options.push(MailParameter::Body(MailBodyParameter::EightBitMime));
+        }
+
+        let envelope = if !email.requires_smtp_utf8() {
+            email.envelope().clone()
+        } else if server_info.supports_feature(Extension::SmtpUtfEight) && self.smtp_utf8 {
+            mail_options.push(MailParameter::SmtpUtfEight);
+            email.envelope().clone()
+        } else if email.has_utf8_headers() {
+            return Err(Error::SmtpUtf8Required);
+        } else {
+            // Without SMTPUTF8, domains are sent as A-labels and UTF-8 local parts can not
+            // be sent at all.
+            email.envelope().to_ascii()?
+        };
+
+        Ok(Transaction {
+            envelope,
+            mail_options,
+            codec,
+        })
+    }
+
+    /// Wraps `stream` with the response parsing configuration.
+    pub(crate) fn stream<S: BufRead + Write + Unpin>(&self, stream: S) -> SmtpStream<S> {
+        SmtpStream::new(stream)
+            .lenient(self.lenient_parsing)
+            .response_limits(self.max_response_line_length, self.max_response_size)
+            .lossy(self.lossy_responses)
+    }
+
+    /// Checks if PIPELINING should be used with the server.
+    pub(crate) fn uses_pipelining(&self, server_info: &ServerInfo) -> bool {
+        server_info.supports_feature(Extension::Pipelining) && self.pipelining
+    }
+}
+
+/// Transaction of a message sent with `SmtpTransport::send_batches`
+#[derive(Debug)]
+pub struct Batch {
+    /// Recipients accepted in this transaction
+    pub recipients: Vec<EmailAddress>,
+    /// Server response after the message content
+    pub response: Response,
+}
+
+/// Failure of `SmtpTransport::send_batches`
+///
+/// The message was delivered to the recipients of the batches completed before the
+/// failure, so they must be left out when it is sent again.
+#[derive(thiserror::Error, Debug)]
+#[error("{error} ({} batches delivered)", .delivered.len())]
+pub struct BatchError {
+    /// Transactions completed before the failure
+    pub delivered: Vec<Batch>,
+    /// Cause of the failure
+    #[source]
+    pub error: Error,
+}
+
+/// Outcome of an ETRN request
+///
+/// [RFC 1985, section 5](https://tools.ietf.org/html/rfc1985#section-5)
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum EtrnOutcome {
+    /// Queuing for the node started, 250
+    Started,
+    /// No messages waiting for the node, 251
+    NoMessages,
+    /// Pending messages for the node started, 252
+    PendingStarted,
+    /// Pending messages for the node started, with their number when the server gives it, 253
+    PendingCount(Option<usize>),
+    /// The server is unable to queue messages for the node, 458
+    Unavailable,
+    /// The node is not allowed, 459
+    NotAllowed,
+}
+
+/// Outcome of an ATRN request
+///
+/// [RFC 2645, section 4.2](https://tools.ietf.org/html/rfc2645#section-4.2)
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum AtrnOutcome {
+    /// The server has mail for the client, and the connection is reversed, 250
+    Reversed,
+    /// The server has no mail for the client, 453
+    NoMail,
+}
+
+/// Outcome of a VRFY request
+///
+/// [RFC 5321, section 3.5.3](https://tools.ietf.org/html/rfc5321#section-3.5.3)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum VrfyOutcome {
+    /// The mailbox exists, with its full address when the server gives it, 250
+    Verified(Option<Mailbox>),
+    /// The user is not local, and mail is forwarded to the given mailbox, 251
+    Forwarded(Option<Mailbox>),
+    /// The server cannot verify the user, but accepts mail for it, 252
+    CannotVerify,
+}
+
 /// Structure that implements the high level SMTP client
 #[derive(Debug)]
 pub struct SmtpTransport<S: BufRead + Write + Unpin> {
@@ -98,19 +377,26 @@ pub struct SmtpTransport<S: BufRead + Write + Unpin> {
     client_info: SmtpClient,
     /// Low level client
     stream: SmtpStream<S>,
+    /// Greeting sent by the server, if expected
+    greeting: Option<Response>,
+    /// Response to EHLO
+    ehlo_response: Response,
+    /// Mechanism of the successful authentication
+    authenticated: Option<Mechanism>,
+    /// Number of MAIL commands accepted by the server
+    transactions: usize,
 }
 
 impl<S: BufRead + Write + Unpin> SmtpTransport<S> {
     /// Creates a new SMTP transport and connects.
     pub async fn new(builder: SmtpClient, stream: S) -> Result<Self, Error> {
-        let mut stream = SmtpStream::new(stream);
-        if builder.expect_greeting {
-            let _greeting = stream.read_response().await?;
-        }
-        let ehlo_response = stream
-            .ehlo(ClientId::new(builder.hello_name.to_string()))
-            .await?;
-        let server_info = ServerInfo::from_response(&ehlo_response)?;
+        let mut stream = builder.stream(stream);
+        let greeting = if builder.expect_greeting {
+            Some(stream.read_response().await?)
+        } else {
+            None
+        };
+        let (ehlo_response, server_info) = hello(&mut stream, &builder).await?;
 
         // Print server information
         debug!("server {}", server_info);
@@ -119,10 +405,229 @@ impl<S: BufRead + Write + Unpin> SmtpTransport<S> {
             server_info,
             client_info: builder,
             stream,
+            greeting,
+            ehlo_response,
+            authenticated: None,
+            transactions: 0,
         };
         Ok(transport)
     }
 
+    /// Sends XCLIENT with the attributes of the original client, as a trusted proxy.
+    ///
+    /// At least one attribute is required.
+    /// The server resets the session: the response to XCLIENT is its new greeting, and
+    /// EHLO is sent again to refresh the server information. A previous authentication
+    /// is lost.
+    pub async fn xclient(&mut self, attributes: Vec<ClientAttribute>) -> SmtpResult {
+        check_attributes(
+            "XCLIENT",
+            &attributes,
+            self.supports_feature(Extension::Xclient),
+            self.server_info.xclient_attributes(),
+        )?;
+
+        let greeting = self.stream.command(XclientCommand::new(attributes)).await?;
+        let (ehlo_response, server_info) = hello(&mut self.stream, &self.client_info).await?;
+        debug!("server {}", server_info);
+
+        self.greeting = Some(greeting.clone());
+        self.ehlo_response = ehlo_response;
+        self.server_info = server_info;
+        self.authenticated = None;
+        Ok(greeting)
+    }
+
+    /// Sends XFORWARD with the attributes of the original client, as a trusted proxy.
+    ///
+    /// At least one attribute is required.
+    /// The attributes apply to the next mail transaction.
+    pub async fn xforward(&mut self, attributes: Vec<ClientAttribute>) -> SmtpResult {
+        check_attributes(
+            "XFORWARD",
+            &attributes,
+            self.supports_feature(Extension::Xforward),
+            self.server_info.xforward_attributes(),
+        )?;
+
+        self.stream.command(XforwardCommand::new(attributes)).await
+    }
+
+    /// Asks the server to start delivering the messages queued for the given node.
+    ///
+    /// The node name must be a single word, without spaces, commas or line breaks.
+    pub async fn etrn(&mut self, node: EtrnNode) -> Result<EtrnOutcome, Error> {
+        if !self.supports_feature(Extension::Etrn) {
+            return Err(Error::UnsupportedCommand("ETRN"));
+        }
+        let (EtrnNode::Domain(name) | EtrnNode::Subdomains(name) | EtrnNode::Queue(name)) = &node;
+        check_word("ETRN", name)?;
+
+        let response = match self.stream.command(EtrnCommand::new(node)).await {
+            Ok(response) | Err(Error::Transient(response)) => response,
+            Err(err) => return Err(err),
+        };
+        if response.has_code(250) {
+            Ok(EtrnOutcome::Started)
+        } else if response.has_code(251) {
+            Ok(EtrnOutcome::NoMessages)
+        } else if response.has_code(252) {
+            Ok(EtrnOutcome::PendingStarted)
+        } else if response.has_code(253) {
+            let count = response
+                .first_line()
+                .and_then(|line| line.split_whitespace().find_map(|word| word.parse().ok()));
+            Ok(EtrnOutcome::PendingCount(count))
+        } else if response.has_code(458) {
+            Ok(EtrnOutcome::Unavailable)
+        } else if response.has_code(459) {
+            Ok(EtrnOutcome::NotAllowed)
+        } else if response.is_positive() {
+            Err(Error::ResponseParsing("Unexpected ETRN response"))
+        } else {
+            Err(response.into())
+        }
+    }
+
+    /// Asks the server to reverse the connection and deliver the mail for the given
+    /// domains, or for the domains of the authenticated client if none are given.
+    /// The domains must not contain spaces, commas or line breaks.
+    ///
+    /// On `AtrnOutcome::Reversed`, the client becomes the server of the session: the
+    /// stream returned by `into_inner` should be served, e.g. with `SmtpServer`.
+    pub async fn atrn(&mut self, domains: Vec<String>) -> Result<AtrnOutcome, Error> {
+        if !self.supports_feature(Extension::Atrn) {
+            return Err(Error::UnsupportedCommand("ATRN"));
+        }
+        for domain in &domains {
+            check_word("ATRN", domain)?;
+        }
+
+        match self.stream.command(AtrnCommand::new(domains)).await {
+            Ok(response) if response.has_code(250) => Ok(AtrnOutcome::Reversed),
+            Ok(_) => Err(Error::ResponseParsing("Unexpected ATRN response")),
+            Err(Error::Transient(response)) if response.has_code(453) => Ok(AtrnOutcome::NoMail),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asks the server to verify a user name or mailbox.
+    pub async fn vrfy(&mut self, argument: String) -> Result<VrfyOutcome, Error> {
+        let response = self.stream.command(VrfyCommand::new(argument)).await?;
+        let line = response.first_line().map(strip_status_code);
+        if response.has_code(250) {
+            Ok(VrfyOutcome::Verified(
+                line.and_then(|line| line.parse().ok()),
+            ))
+        } else if response.has_code(251) {
+            // The text is free-form, e.g. "User not local; will forward to <jones@xyz.com>".
+            let mailbox = line
+                .and_then(|line| line.rfind('<').map(|start| &line[start..]))
+                .and_then(|mailbox| mailbox.parse().ok());
+            Ok(VrfyOutcome::Forwarded(mailbox))
+        } else if response.has_code(252) {
+            Ok(VrfyOutcome::CannotVerify)
+        } else {
+            Err(Error::ResponseParsing("Unexpected VRFY response"))
+        }
+    }
+
+    /// Asks the server to expand a mailing list, and returns its members.
+    pub async fn expn(&mut self, argument: String) -> Result<Vec<Mailbox>, Error> {
+        let response = self.stream.command(ExpnCommand::new(argument)).await?;
+        response
+            .message
+            .iter()
+            .map(|line| {
+                strip_status_code(line)
+                    .parse()
+                    .map_err(|_| Error::ResponseParsing("Invalid mailbox in EXPN response"))
+            })
+            .collect()
+    }
+
+    /// Asks the server for help, optionally about the given topic, and returns the text.
+    pub async fn help(&mut self, topic: Option<String>) -> Result<String, Error> {
+        let response = self.stream.command(HelpCommand::new(topic)).await?;
+        Ok(response.message.join("\n"))
+    }
+
+    /// Sends NOOP, e.g. to keep the connection alive.
+    pub async fn noop(&mut self) -> Result<(), Error> {
+        self.stream.command(NoopCommand).await?;
+
+        Ok(())
+    }
+
+    /// Sends EHLO again, and refreshes the information about the server.
+    ///
+    /// Use this after raw commands changing the capabilities of the server.
+    pub async fn ehlo(&mut self) -> SmtpResult {
+        let (ehlo_response, server_info) = hello(&mut self.stream, &self.client_info).await?;
+        debug!("server {}", server_info);
+
+        self.ehlo_response = ehlo_response.clone();
+        self.server_info = server_info;
+        Ok(ehlo_response)
+    }
+
+    /// Sends a raw command, e.g. a vendor-specific one, and returns the server response.
+    ///
+    /// The command is a single line, CRLF is appended if missing. Commands are sent as is:
+    /// mail transactions and session changes made this way are not tracked by the transport.
+    pub async fn command(&mut self, command: impl Display) -> SmtpResult {
+        self.send_command(command).await?;
+        self.read_response().await
+    }
+
+    /// Sends a raw command without waiting for the response, e.g. to pipeline commands.
+    ///
+    /// Each command must be followed by a call to `read_response`.
+    pub async fn send_command(&mut self, command: impl Display) -> Result<(), Error> {
+        let line = command_line(command)?;
+        self.stream.send_command(line).await
+    }
+
+    /// Reads the response to a command sent with `send_command`.
+    pub async fn read_response(&mut self) -> SmtpResult {
+        self.stream.read_response().await
+    }
+
+    /// Returns the inner stream, e.g. to serve it after ATRN.
+    pub fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    /// Returns the information about the server
+    pub fn server_info(&self) -> &ServerInfo {
+        &self.server_info
+    }
+
+    /// Returns the greeting sent by the server
+    ///
+    /// It is `None` if the client was built `without_greeting`.
+    pub fn greeting(&self) -> Option<&Response> {
+        self.greeting.as_ref()
+    }
+
+    /// Returns the response to EHLO, with one message line per line sent by the server
+    ///
+    /// If the server rejected EHLO, this is the response to HELO.
+    pub fn ehlo_response(&self) -> &Response {
+        &self.ehlo_response
+    }
+
+    /// Tells if the stream is assumed to be protected with TLS, as set with
+    /// `SmtpClient::assume_tls`
+    pub fn is_tls(&self) -> bool {
+        self.client_info.assume_tls
+    }
+
+    /// Returns the mechanism used by the last successful authentication, if any
+    pub fn authenticated(&self) -> Option<Mechanism> {
+        self.authenticated
+    }
+
     /// Try to login with the given accepted mechanisms.
     pub async fn try_login(
         &mut self,
@@ -155,6 +660,14 @@ impl<S: BufRead + Write + Unpin> SmtpTransport<S> {
         Ok(self.stream.into_inner())
     }
 
+    /// Returns the deviations from RFC 5321 accepted in the server responses so far
+    ///
+    /// Only the last 64 are kept. This is always empty unless
+    /// `SmtpClient::lenient_parsing` is enabled.
+    pub fn response_warnings(&self) -> &[ResponseWarning] {
+        self.stream.warnings()
+    }
+
     fn supports_feature(&self, keyword: Extension) -> bool {
         self.server_info.supports_feature(keyword)
     }
@@ -190,82 +703,1283 @@ impl<S: BufRead + Write + Unpin> SmtpTransport<S> {
         if challenges == 0 {
             Err(Error::ResponseParsing("Unexpected number of challenges"))
         } else {
+            self.authenticated = Some(mechanism);
             Ok(response)
         }
     }
 
+    /// Returns the number of mail transactions started in this session
+    ///
+    /// Only the transactions whose `MAIL` command was accepted are counted.
+    pub fn transactions(&self) -> usize {
+        self.transactions
+    }
+
+    /// Tells if the session reached the `MAILMAX` limit advertised by the server
+    ///
+    /// Once it is reached, `send` fails with `Error::MailMaxReached`. The transport does
+    /// not reconnect by itself: the caller, typically a connection pool, has to open a new
+    /// connection.
+    pub fn mail_limit_reached(&self) -> bool {
+        matches!(self.server_info.limits().mail_max, Some(max) if self.transactions >= max)
+    }
+
     /// Sends an email.
+    ///
+    /// The message may be sent in several transactions, see `send_batches`. The response
+    /// of the last one is returned. Use `send_batches` to know which recipients got the
+    /// message when a transaction after the first one fails.
     pub async fn send(&mut self, email: SendableEmail) -> SmtpResult {
-        // Mail
-        let mut mail_options = vec![];
+        let mut batches = self.send_batches(email).await.map_err(|err| err.error)?;
+        batches
+            .pop()
+            .map(|batch| batch.response)
+            .ok_or(Error::Client("no recipients"))
+    }
 
-        if self.supports_feature(Extension::EightBitMime) {
-            mail_options.push(MailParameter::Body(MailBodyParameter::EightBitMime));
+    /// Sends an email, in as many transactions as needed, and reports each of them.
+    ///
+    /// If the server advertises `LIMITS` and the envelope has more recipients than a
+    /// transaction allows, the recipients are split upfront. If the server replies 452 to
+    /// a recipient, the transaction is completed with the accepted recipients and the
+    /// other ones are sent in a follow-up transaction, as described in
+    /// [RFC 5321, section 4.5.3.1.10](https://tools.ietf.org/html/rfc5321#section-4.5.3.1.10).
+    /// A message sent more than once is kept in memory while it is streamed the first time.
+    ///
+    /// On failure, the batches already delivered are returned with the error.
+    pub async fn send_batches(&mut self, email: SendableEmail) -> Result<Vec<Batch>, BatchError> {
+        let mut batches = vec![];
+        match self.deliver(email, &mut batches).await {
+            Ok(()) => Ok(batches),
+            Err(error) => Err(BatchError {
+                delivered: batches,
+                error,
+            }),
         }
+    }
 
-        if self.supports_feature(Extension::SmtpUtfEight) && self.client_info.smtp_utf8 {
-            mail_options.push(MailParameter::SmtpUtfEight);
+    /// Sends the transactions of `send_batches`, collecting the completed ones.
+    async fn deliver(
+        &mut self,
+        email: SendableEmail,
+        batches: &mut Vec<Batch>,
+    ) -> Result<(), Error> {
+        let Transaction {
+            envelope,
+            mail_options,
+            codec,
+        } = self
+            .client_info
+            .transaction(&self.server_info, &email)
+            .await?;
+
+        let limits = self.server_info.limits();
+        let mut pending: VecDeque<_> = split_recipients(envelope.to(), &limits).into();
+        if let Some(mail_max) = limits.mail_max {
+            if self.transactions + pending.len() > mail_max {
+                return Err(Error::MailMaxReached);
+            }
+        }
+
+        let mut message = Some(email.message());
+        let mut content = vec![];
+
+        while let Some(to) = pending.pop_front() {
+            if self.mail_limit_reached() {
+                return Err(Error::MailMaxReached);
+            }
+            let (accepted, deferred) = self
+                .start_transaction(envelope.from(), &to, mail_options.clone())
+                .await?;
+            if !deferred.is_empty() {
+                pending.push_front(deferred);
+            }
+
+            // Stream the message, and keep a copy of it if it is sent again.
+            let response = match message.take() {
+                Some(message) if pending.is_empty() => self.stream.message(message, codec).await?,
+                Some(message) => {
+                    self.stream
+                        .message_copy(message, codec, &mut content)
+                        .await?
+                }
+                None => self.stream.message(&content[..], codec).await?,
+            };
+            debug!(
+                "status=sent ({})",
+                response
+                    .message
+                    .first()
+                    .map_or("no response", String::as_str)
+            );
+            batches.push(Batch {
+                recipients: accepted,
+                response,
+            });
         }
 
-        let pipelining =
-            self.supports_feature(Extension::Pipelining) && self.client_info.pipelining;
+        Ok(())
+    }
+
+    /// Starts a mail transaction, up to the DATA command.
+    ///
+    /// Returns the accepted recipients, and those deferred with a 452 reply.
+    async fn start_transaction(
+        &mut self,
+        from: Option<&EmailAddress>,
+        to: &[EmailAddress],
+        mail_options: Vec<MailParameter>,
+    ) -> Result<(Vec<EmailAddress>, Vec<EmailAddress>), Error> {
+        let pipelining = self.client_info.uses_pipelining(&self.server_info);
+
+        let mut accepted = vec![];
+        let mut deferred = vec![];
+        let mut deferral = None;
 
         if pipelining {
             self.stream
-                .send_command(MailCommand::new(
-                    email.envelope().from().cloned(),
-                    mail_options,
-                ))
+                .send_command(MailCommand::new(from.cloned(), mail_options))
                 .await?;
-            let mut sent_commands = 1;
 
             // Recipient
-            for to_address in email.envelope().to() {
+            for to_address in to {
                 self.stream
                     .send_command(RcptCommand::new(to_address.clone(), vec![]))
                     .await?;
-                sent_commands += 1;
             }
 
             // Data
             self.stream.send_command(DataCommand).await?;
-            sent_commands += 1;
 
-            for _ in 0..sent_commands {
-                self.stream.read_response().await?;
+            // Every reply is read before acting on them, to keep the stream in sync.
+            let mut failure = None;
+            let mail_accepted = match self.stream.read_response().await {
+                Ok(_) => true,
+                Err(err @ (Error::Transient(_) | Error::Permanent(_))) => {
+                    failure = Some(err);
+                    false
+                }
+                Err(err) => return Err(err),
+            };
+            if mail_accepted {
+                self.transactions += 1;
+            }
+            for to_address in to {
+                match self.stream.read_response().await {
+                    Ok(_) => accepted.push(to_address.clone()),
+                    Err(Error::Transient(response)) if response.has_code(452) => {
+                        deferred.push(to_address.clone());
+                        deferral.get_or_insert(Error::Transient(response));
+                    }
+                    Err(err @ (Error::Transient(_) | Error::Permanent(_))) => {
+                        failure.get_or_insert(err);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            let data_response = self.stream.read_response().await;
+            if accepted.is_empty() && failure.is_none() {
+                // DATA is rejected without recipients.
+                failure = deferral;
+            }
+
+            if let Some(err) = failure {
+                match data_response {
+                    // The transaction can only be ended by sending the end of data.
+                    // See [RFC 2920, section 3.1](https://tools.ietf.org/html/rfc2920#section-3.1).
+                    Ok(_) => {
+                        self.stream.message(&[][..], ClientCodec::new()).await.ok();
+                    }
+                    Err(Error::Transient(_) | Error::Permanent(_)) if mail_accepted => {
+                        self.stream.command(RsetCommand).await?;
+                    }
+                    Err(Error::Transient(_) | Error::Permanent(_)) => {}
+                    Err(err) => return Err(err),
+                }
+                return Err(err);
             }
+            data_response?;
         } else {
             self.stream
-                .command(MailCommand::new(
-                    email.envelope().from().cloned(),
-                    mail_options,
-                ))
+                .command(MailCommand::new(from.cloned(), mail_options))
                 .await?;
+            self.transactions += 1;
 
             // Recipient
-            for to_address in email.envelope().to() {
-                self.stream
+            for (idx, to_address) in to.iter().enumerate() {
+                match self
+                    .stream
                     .command(RcptCommand::new(to_address.clone(), vec![]))
-                    .await?;
+                    .await
+                {
+                    Ok(_) => accepted.push(to_address.clone()),
+                    Err(Error::Transient(response)) if response.has_code(452) => {
+                        // The remaining recipients go to the next transaction.
+                        deferred.extend_from_slice(&to[idx..]);
+                        deferral = Some(Error::Transient(response));
+                        break;
+                    }
+                    Err(err @ (Error::Transient(_) | Error::Permanent(_))) => {
+                        self.stream.command(RsetCommand).await?;
+                        return Err(err);
+                    }
+                    Err(err) => return Err(err),
+                }
                 // Log the rcpt command
                 debug!("to=<{}>", to_address);
             }
+            if accepted.is_empty() {
+                if let Some(err) = deferral {
+                    self.stream.command(RsetCommand).await?;
+                    return Err(err);
+                }
+            }
 
             // Data
             self.stream.command(DataCommand).await?;
         }
 
-        let res = self.stream.message(email.message()).await;
-
-        // Message content
-        if let Ok(result) = &res {
-            // Log the message
+        if !deferred.is_empty() {
             debug!(
-                "status=sent ({})",
-                result.message.get(0).unwrap_or(&"no response".to_string())
+                "{} recipients deferred to a new transaction",
+                deferred.len()
             );
         }
+        Ok((accepted, deferred))
+    }
+}
+
+/// Renders a raw command line, rejecting CR and LF before the final CRLF.
+fn command_line(command: impl Display) -> Result<String, Error> {
+    let mut line = command.to_string();
+    let terminated = line.ends_with("\r\n");
+    let text = if terminated {
+        &line[..line.len() - 2]
+    } else {
+        &line
+    };
+    if text.contains(['\r', '\n']) {
+        return Err(Error::LineBreakInCommand);
+    }
+    if !terminated {
+        line.push_str("\r\n");
+    }
+    Ok(line)
+}
+
+/// Strips the enhanced status code from a response line, e.g. `2.1.5`.
+///
+/// [RFC 3463](https://tools.ietf.org/html/rfc3463)
+fn strip_status_code(line: &str) -> &str {
+    match line.split_once(' ') {
+        Some((code, text))
+            if code.split('.').count() == 3
+                && code
+                    .split('.')
+                    .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())) =>
+        {
+            text
+        }
+        _ => line,
+    }
+}
+
+/// Sends EHLO, or HELO if the server does not know EHLO.
+async fn hello<S: BufRead + Write + Unpin>(
+    stream: &mut SmtpStream<S>,
+    client: &SmtpClient,
+) -> Result<(Response, ServerInfo), Error> {
+    let client_id = ClientId::new(client.hello_name.to_string());
+    match stream.ehlo(client_id.clone()).await {
+        Ok(response) => {
+            let server_info = ServerInfo::from_response(&response)?;
+            Ok((response, server_info))
+        }
+        // The server does not know EHLO, fall back to HELO without extensions.
+        Err(Error::Permanent(response)) if response.has_code(500) || response.has_code(502) => {
+            info!("EHLO rejected, falling back to HELO");
+            let response = stream.helo(client_id).await?;
+            let server_info = ServerInfo::from_helo_response(&response)?;
+            Ok((response, server_info))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Checks that the server supports XCLIENT or XFORWARD with the given attributes.
+fn check_attributes(
+    command: &'static str,
+    attributes: &[ClientAttribute],
+    supported: bool,
+    names: &[String],
+) -> Result<(), Error> {
+    if !supported {
+        return Err(Error::UnsupportedCommand(command));
+    }
+    if attributes.is_empty() {
+        return Err(Error::Client("no attributes"));
+    }
+    match attributes.iter().find(|attribute| {
+        !names
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(attribute.name()))
+    }) {
+        Some(attribute) => Err(Error::UnsupportedParameter(attribute.name())),
+        None => Ok(()),
+    }
+}
+
+/// Checks that an ETRN node or ATRN domain is a single non-empty word.
+fn check_word(command: &'static str, word: &str) -> Result<(), Error> {
+    if word.is_empty() || word.contains(|c: char| c.is_ascii_whitespace() || c == ',') {
+        return Err(Error::InvalidParameter(command));
+    }
+    Ok(())
+}
+
+/// Checks that the server accepts a `MAIL FROM` parameter set on the message.
+fn check_mail_parameter(server_info: &ServerInfo, parameter: &MailParameter) -> Result<(), Error> {
+    match *parameter {
+        MailParameter::MtPriority(priority) => {
+            if !server_info.supports_feature(Extension::MtPriority) {
+                return Err(Error::UnsupportedParameter("MT-PRIORITY"));
+            }
+            if !(-9..=9).contains(&priority) {
+                return Err(Error::InvalidParameter("MT-PRIORITY"));
+            }
+        }
+        MailParameter::DeliverBy { seconds, mode, .. } => {
+            let min_time = server_info
+                .deliver_by_min_time()
+                .ok_or(Error::UnsupportedParameter("DELIVERBY"))?;
+            if !(-MAX_DELIVER_BY..=MAX_DELIVER_BY).contains(&seconds) {
+                return Err(Error::InvalidParameter("BY"));
+            }
+            // A deadline in return mode must be in the future, and not below the minimum.
+            if mode == DeliverByMode::Return && (seconds <= 0 || (seconds as u64) < min_time) {
+                return Err(Error::InvalidParameter("BY"));
+            }
+        }
+        MailParameter::HoldFor(seconds) => {
+            let future_release = server_info
+                .future_release()
+                .ok_or(Error::UnsupportedParameter("FUTURERELEASE"))?;
+            if Duration::from_secs(seconds) > future_release.max_interval {
+                return Err(Error::InvalidParameter("HOLDFOR"));
+            }
+        }
+        MailParameter::HoldUntil(date) => {
+            let future_release = server_info
+                .future_release()
+                .ok_or(Error::UnsupportedParameter("FUTURERELEASE"))?;
+            let interval = hold_until_interval(date).ok_or(Error::InvalidParameter("HOLDUNTIL"))?;
+            if interval > future_release.max_interval
+                || matches!(future_release.max_date, Some(max_date) if date > max_date)
+            {
+                return Err(Error::InvalidParameter("HOLDUNTIL"));
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Splits the recipients in batches respecting `RCPTMAX` and `RCPTDOMAINMAX`.
+fn split_recipients(recipients: &[EmailAddress], limits: &Limits) -> Vec<Vec<EmailAddress>> {
+    let rcpt_max = limits.rcpt_max.unwrap_or(usize::MAX);
+    let rcpt_domain_max = limits.rcpt_domain_max.unwrap_or(usize::MAX);
+
+    let mut batches: Vec<(Vec<EmailAddress>, Vec<String>)> = vec![];
+    for recipient in recipients {
+        let domain = recipient.domain().unwrap_or_default().to_lowercase();
+        // Add to the first batch with room, to keep the number of transactions low.
+        let batch = batches.iter_mut().find(|(to, domains)| {
+            to.len() < rcpt_max && (domains.contains(&domain) || domains.len() < rcpt_domain_max)
+        });
+        match batch {
+            Some((to, domains)) => {
+                to.push(recipient.clone());
+                if !domains.contains(&domain) {
+                    domains.push(domain);
+                }
+            }
+            None => batches.push((vec![recipient.clone()], vec![domain])),
+        }
+    }
+    batches.into_iter().map(|(to, _)| to).collect()
+}
 
-        res
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::async_test;
+    use crate::extension::MAX_HOLD_UNTIL;
+    use crate::mock::MockStream;
+    use crate::Envelope;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    async fn send(ehlo: &str, email: SendableEmail) -> (SmtpResult, String) {
+        let stream = MockStream::new(format!(
+            "220 mx.example.org ESMTP\r\n{ehlo}250 OK\r\n250 OK\r\n354 Go ahead\r\n250 OK\r\n"
+        ));
+        let output = stream.output();
+        let client = SmtpClient::new().smtp_utf8(true);
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+        let result = transport.send(email).await;
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        (result, output)
+    }
+
+    /// Asserts that the email is rejected with the given error before the transaction.
+    macro_rules! assert_rejected {
+        ($ehlo:expr, $email:expr, $error:pat) => {
+            let (result, output) = send($ehlo, $email).await;
+            assert!(matches!(result, Err($error)), "{:?}", result);
+            assert!(!output.contains("MAIL FROM"));
+        };
+    }
+
+    fn email(from: &str, to: &str) -> SendableEmail {
+        SendableEmail::new(
+            Envelope::new(Some(from.parse().unwrap()), vec![to.parse().unwrap()]).unwrap(),
+            "Hello world",
+        )
     }
+
+    fn three_recipients() -> Envelope {
+        Envelope::new(
+            Some("user@example.org".parse().unwrap()),
+            vec![
+                "a@example.org".parse().unwrap(),
+                "b@example.org".parse().unwrap(),
+                "c@example.org".parse().unwrap(),
+            ],
+        )
+        .unwrap()
+    }
+
+    async_test! { test_send_long_line, {
+        let stream = MockStream::new(
+            "220 mx.example.org ESMTP\r\n250 mx.example.org\r\n",
+        );
+        let output = stream.output();
+        let client = SmtpClient::new().reject_long_lines(true);
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+
+        let message = format!("Subject: test\r\n{}\r\n", "a".repeat(1000));
+        let email = SendableEmail::new(
+            Envelope::new(None, vec!["root@example.org".parse().unwrap()]).unwrap(),
+            message,
+        );
+        assert!(matches!(
+            transport.send(email).await,
+            Err(Error::LineTooLong(2))
+        ));
+        assert!(!String::from_utf8_lossy(&output.lock().unwrap()).contains("MAIL FROM"));
+    }}
+
+    async_test! { test_send_bare_line_ending, {
+        let stream = MockStream::new(
+            "220 mx.example.org ESMTP\r\n250 mx.example.org\r\n\
+             250 OK\r\n250 OK\r\n354 Go ahead\r\n250 OK\r\n",
+        );
+        let output = stream.output();
+        let client = SmtpClient::new().line_endings(LineEndings::Reject);
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+
+        let email = |message: &str| {
+            SendableEmail::new(
+                Envelope::new(None, vec!["root@example.org".parse().unwrap()]).unwrap(),
+                message,
+            )
+        };
+        assert!(matches!(
+            transport.send(email("Subject: test\r\n\r\nbare\nline\r\n")).await,
+            Err(Error::BareLineEnding(3))
+        ));
+        assert!(!String::from_utf8_lossy(&output.lock().unwrap()).contains("MAIL FROM"));
+
+        // The transport is still usable for the re-encoded message.
+        transport
+            .send(email("Subject: test\r\n\r\nbare\r\nline\r\n"))
+            .await
+            .unwrap();
+    }}
+
+    const EHLO_UTF8: &str = "250-mx.example.org\r\n250 SMTPUTF8\r\n";
+    const EHLO_ASCII: &str = "250 mx.example.org\r\n";
+
+    async_test! { test_send_smtp_utf8_only_when_needed, {
+        let (result, output) = send(EHLO_UTF8, email("user@example.org", "root@example.org")).await;
+        assert!(result.is_ok());
+        assert!(output.contains("MAIL FROM:<user@example.org>\r\n"));
+
+        let (result, output) = send(EHLO_UTF8, email("bjørn@example.org", "root@example.org")).await;
+        assert!(result.is_ok());
+        assert!(output.contains("MAIL FROM:<bjørn@example.org> SMTPUTF8\r\n"));
+
+        let (result, output) = send(
+            EHLO_UTF8,
+            email("user@example.org", "root@example.org").utf8_headers(true),
+        )
+        .await;
+        assert!(result.is_ok());
+        assert!(output.contains("MAIL FROM:<user@example.org> SMTPUTF8\r\n"));
+    }}
+
+    async_test! { test_send_smtp_utf8_unavailable, {
+        let (result, output) = send(EHLO_ASCII, email("user@example.org", "root@bücher.example")).await;
+        assert!(result.is_ok());
+        assert!(output.contains("MAIL FROM:<user@example.org>\r\n"));
+        assert!(output.contains("RCPT TO:<root@xn--bcher-kva.example>\r\n"));
+
+        let (result, output) = send(EHLO_ASCII, email("bjørn@example.org", "root@example.org")).await;
+        assert!(matches!(result, Err(Error::SmtpUtf8Required)));
+        assert!(!output.contains("MAIL FROM"));
+
+        let (result, output) = send(
+            EHLO_ASCII,
+            email("user@example.org", "root@example.org").utf8_headers(true),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::SmtpUtf8Required)));
+        assert!(!output.contains("MAIL FROM"));
+    }}
+
+    async_test! { test_lenient_parsing, {
+        const SESSION: &str = "220 mx.example.org ESMTP\r\n250-mx.example.org\n250 PIPELINING\n\
+             250\r\n250 OK\r\n354 Go ahead\r\n250 OK\r\n";
+
+        let stream = MockStream::new("220 mx.example.org ESMTP\r\n250\r\n");
+        assert!(SmtpTransport::new(SmtpClient::new(), stream).await.is_err());
+
+        let client = SmtpClient::new().lenient_parsing(true);
+        let mut transport = SmtpTransport::new(client, MockStream::new(SESSION))
+            .await
+            .unwrap();
+        assert_eq!(
+            transport.response_warnings(),
+            [ResponseWarning::BareLineFeed, ResponseWarning::BareLineFeed]
+        );
+        transport
+            .send(email("user@example.org", "root@example.org"))
+            .await
+            .unwrap();
+        assert_eq!(
+            transport.response_warnings().last(),
+            Some(&ResponseWarning::MissingSeparator)
+        );
+    }}
+
+    async_test! { test_connection_metadata, {
+        let stream = MockStream::new(
+            "220 mx.example.org ESMTP Quirky 1.0\r\n250-mx.example.org\r\n250 AUTH PLAIN\r\n\
+             235 2.7.0 Authentication successful\r\n",
+        );
+        let client = SmtpClient::new().assume_tls(true);
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+
+        assert_eq!(
+            transport.greeting().and_then(Response::first_line),
+            Some("mx.example.org ESMTP Quirky 1.0")
+        );
+        assert_eq!(
+            transport.ehlo_response().message,
+            ["mx.example.org", "AUTH PLAIN"]
+        );
+        assert!(transport.is_tls());
+        assert_eq!(transport.authenticated(), None);
+
+        let credentials = Credentials::new("user".to_string(), "password".to_string());
+        transport
+            .try_login(&credentials, &[Mechanism::Login, Mechanism::Plain])
+            .await
+            .unwrap();
+        assert_eq!(transport.authenticated(), Some(Mechanism::Plain));
+
+        let stream = MockStream::new("250 mx.example.org\r\n");
+        let transport = SmtpTransport::new(SmtpClient::new().without_greeting(), stream)
+            .await
+            .unwrap();
+        assert!(transport.greeting().is_none());
+        assert!(!transport.is_tls());
+    }}
+
+    async_test! { test_helo_fallback, {
+        let stream = MockStream::new(
+            "220 old.example.org SMTP\r\n502 Command not implemented\r\n250 old.example.org\r\n\
+             250 OK\r\n250 OK\r\n354 Go ahead\r\n250 OK\r\n",
+        );
+        let output = stream.output();
+        let client = SmtpClient::new().hello_name(ClientId::Domain("client.example.org".into()));
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+        assert_eq!(transport.server_info().name, "old.example.org");
+        assert!(transport.server_info().features.is_empty());
+
+        let credentials = Credentials::new("user".to_string(), "password".to_string());
+        transport
+            .try_login(&credentials, &[Mechanism::Plain])
+            .await
+            .unwrap();
+        transport
+            .send(email("user@example.org", "root@example.org"))
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "EHLO client.example.org\r\nHELO client.example.org\r\n\
+             MAIL FROM:<user@example.org>\r\nRCPT TO:<root@example.org>\r\nDATA\r\n\
+             Hello world\r\n.\r\n"
+        );
+
+        let stream = MockStream::new("220 mx.example.org\r\n554 Go away\r\n");
+        assert!(matches!(
+            SmtpTransport::new(SmtpClient::new(), stream).await,
+            Err(Error::Permanent(_))
+        ));
+    }}
+
+    #[test]
+    fn test_split_recipients() {
+        let recipients: Vec<EmailAddress> = ["a@one.org", "b@two.org", "c@ONE.org", "d@three.org"]
+            .iter()
+            .map(|to| to.parse().unwrap())
+            .collect();
+        let split = |rcpt_max, rcpt_domain_max| {
+            split_recipients(
+                &recipients,
+                &Limits {
+                    mail_max: None,
+                    rcpt_max,
+                    rcpt_domain_max,
+                },
+            )
+            .iter()
+            .map(|batch| batch.iter().map(ToString::to_string).collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            split(None, None),
+            [["a@one.org", "b@two.org", "c@ONE.org", "d@three.org"]]
+        );
+        assert_eq!(
+            split(Some(3), None),
+            [
+                vec!["a@one.org", "b@two.org", "c@ONE.org"],
+                vec!["d@three.org"]
+            ]
+        );
+        assert_eq!(
+            split(None, Some(1)),
+            [
+                vec!["a@one.org", "c@ONE.org"],
+                vec!["b@two.org"],
+                vec!["d@three.org"]
+            ]
+        );
+    }
+
+    async_test! { test_send_limits, {
+        let stream = MockStream::new(
+            "220 mx.example.org ESMTP\r\n250-mx.example.org\r\n250 LIMITS RCPTMAX=2 MAILMAX=3\r\n\
+             250 OK\r\n250 OK\r\n250 OK\r\n354 Go ahead\r\n250 First\r\n\
+             250 OK\r\n250 OK\r\n354 Go ahead\r\n250 Second\r\n",
+        );
+        let output = stream.output();
+        let mut transport = SmtpTransport::new(SmtpClient::new(), stream).await.unwrap();
+
+        let email = SendableEmail::new(three_recipients(), "Hello world");
+        let response = transport.send(email).await.unwrap();
+        assert_eq!(response.first_line(), Some("Second"));
+        assert_eq!(transport.transactions(), 2);
+        assert!(!transport.mail_limit_reached());
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "EHLO [127.0.0.1]\r\n\
+             MAIL FROM:<user@example.org>\r\n\
+             RCPT TO:<a@example.org>\r\nRCPT TO:<b@example.org>\r\nDATA\r\n\
+             Hello world\r\n.\r\n\
+             MAIL FROM:<user@example.org>\r\nRCPT TO:<c@example.org>\r\nDATA\r\n\
+             Hello world\r\n.\r\n"
+        );
+
+        // The next message would need two more transactions.
+        let email = SendableEmail::new(three_recipients(), "Hello world");
+        assert!(matches!(
+            transport.send(email).await,
+            Err(Error::MailMaxReached)
+        ));
+    }}
+
+    async_test! { test_transactions_count_accepted_mail, {
+        let stream = MockStream::new(
+            "220 mx.example.org ESMTP\r\n250 mx.example.org\r\n550 Sender rejected\r\n",
+        );
+        let mut transport = SmtpTransport::new(SmtpClient::new(), stream).await.unwrap();
+
+        assert!(transport
+            .send(email("user@example.org", "root@example.org"))
+            .await
+            .is_err());
+        assert_eq!(transport.transactions(), 0);
+    }}
+
+    async_test! { test_send_batches_on_452, {
+        for (ehlo, replies) in [
+            (
+                "250 mx.example.org\r\n",
+                "250 OK\r\n250 OK\r\n452 4.5.3 Too many recipients\r\n354 Go ahead\r\n250 First\r\n",
+            ),
+            (
+                "250-mx.example.org\r\n250 PIPELINING\r\n",
+                "250 OK\r\n250 OK\r\n452 4.5.3 Too many recipients\r\n\
+                 452 4.5.3 Too many recipients\r\n354 Go ahead\r\n250 First\r\n",
+            ),
+        ] {
+            let stream = MockStream::new(format!(
+                "220 mx.example.org ESMTP\r\n{ehlo}{replies}\
+                 250 OK\r\n250 OK\r\n250 OK\r\n354 Go ahead\r\n250 Second\r\n"
+            ));
+            let output = stream.output();
+            let mut transport = SmtpTransport::new(SmtpClient::new(), stream).await.unwrap();
+
+            let email =
+                SendableEmail::new_with_reader(three_recipients(), Box::new(&b"Hello world"[..]));
+            let batches = transport.send_batches(email).await.unwrap();
+
+            let recipients: Vec<Vec<String>> = batches
+                .iter()
+                .map(|batch| batch.recipients.iter().map(ToString::to_string).collect())
+                .collect();
+            assert_eq!(
+                recipients,
+                [vec!["a@example.org"], vec!["b@example.org", "c@example.org"]]
+            );
+            assert_eq!(batches[0].response.first_line(), Some("First"));
+            assert_eq!(batches[1].response.first_line(), Some("Second"));
+            assert_eq!(transport.transactions(), 2);
+
+            let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+            assert_eq!(output.matches("Hello world\r\n.\r\n").count(), 2);
+            assert!(output.ends_with(
+                "MAIL FROM:<user@example.org>\r\nRCPT TO:<b@example.org>\r\n\
+                 RCPT TO:<c@example.org>\r\nDATA\r\nHello world\r\n.\r\n"
+            ));
+        }
+    }}
+
+    async_test! { test_send_after_rejection, {
+        const EHLO_PIPELINING: &str = "250-mx.example.org\r\n250 PIPELINING\r\n";
+        for (ehlo, replies, ending, transactions) in [
+            (
+                EHLO_PIPELINING,
+                "550 Sender rejected\r\n503 No sender\r\n503 No sender\r\n\
+                 503 No sender\r\n503 No recipients\r\n",
+                "RCPT TO:<c@example.org>\r\nDATA\r\n",
+                1,
+            ),
+            (
+                EHLO_PIPELINING,
+                "250 OK\r\n250 OK\r\n550 No such user\r\n250 OK\r\n354 Go ahead\r\n250 OK\r\n",
+                "RCPT TO:<c@example.org>\r\nDATA\r\n.\r\n",
+                2,
+            ),
+            (
+                EHLO_PIPELINING,
+                "250 OK\r\n550 No such user\r\n550 No such user\r\n550 No such user\r\n\
+                 554 No valid recipients\r\n250 OK\r\n",
+                "RCPT TO:<c@example.org>\r\nDATA\r\nRSET\r\n",
+                2,
+            ),
+            (
+                EHLO_ASCII,
+                "250 OK\r\n250 OK\r\n550 No such user\r\n250 OK\r\n",
+                "RCPT TO:<b@example.org>\r\nRSET\r\n",
+                2,
+            ),
+        ] {
+            let stream = MockStream::new(format!(
+                "220 mx.example.org ESMTP\r\n{ehlo}{replies}\
+                 250 OK\r\n250 OK\r\n354 Go ahead\r\n250 Sent\r\n"
+            ));
+            let output = stream.output();
+            let mut transport = SmtpTransport::new(SmtpClient::new(), stream).await.unwrap();
+
+            let rejected = SendableEmail::new(three_recipients(), "Hello world");
+            assert!(matches!(
+                transport.send(rejected).await,
+                Err(Error::Permanent(response)) if response.has_code(550)
+            ));
+            let response = transport
+                .send(email("user@example.org", "root@example.org"))
+                .await
+                .unwrap();
+            assert_eq!(response.first_line(), Some("Sent"));
+            assert_eq!(transport.transactions(), transactions);
+
+            let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+            assert!(output.ends_with(&format!(
+                "{ending}MAIL FROM:<user@example.org>\r\nRCPT TO:<root@example.org>\r\n\
+                 DATA\r\nHello world\r\n.\r\n"
+            )));
+        }
+    }}
+
+    async_test! { test_send_batches_partial_failure, {
+        for (ehlo, second, error) in [
+            (
+                "250 mx.example.org\r\n",
+                "554 5.7.1 Rejected\r\n",
+                "permanent: 5.7.1 Rejected",
+            ),
+            (
+                "250-mx.example.org\r\n250 LIMITS MAILMAX=1\r\n",
+                "",
+                "maximum number of transactions in the session reached",
+            ),
+        ] {
+            let stream = MockStream::new(format!(
+                "220 mx.example.org ESMTP\r\n{ehlo}250 OK\r\n250 OK\r\n\
+                 452 4.5.3 Too many recipients\r\n354 Go ahead\r\n250 First\r\n{second}"
+            ));
+            let mut transport = SmtpTransport::new(SmtpClient::new(), stream).await.unwrap();
+
+            let email = SendableEmail::new(three_recipients(), "Hello world");
+            let err = transport.send_batches(email).await.unwrap_err();
+            assert_eq!(err.error.to_string(), error);
+            assert_eq!(err.delivered.len(), 1);
+            assert_eq!(
+                err.delivered[0].recipients,
+                ["a@example.org".parse::<EmailAddress>().unwrap()]
+            );
+            assert_eq!(err.delivered[0].response.first_line(), Some("First"));
+        }
+    }}
+
+    async_test! { test_send_452_without_accepted_recipient, {
+        let stream = MockStream::new(
+            "220 mx.example.org ESMTP\r\n250 mx.example.org\r\n\
+             250 OK\r\n452 4.3.1 Insufficient system storage\r\n250 OK\r\n",
+        );
+        let output = stream.output();
+        let mut transport = SmtpTransport::new(SmtpClient::new(), stream).await.unwrap();
+
+        let email = SendableEmail::new(three_recipients(), "Hello world");
+        assert!(matches!(
+            transport.send(email).await,
+            Err(Error::Transient(response)) if response.has_code(452)
+        ));
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("RCPT TO:<a@example.org>\r\nRSET\r\n"));
+    }}
+
+    async_test! { test_send_require_tls, {
+        const EHLO_REQUIRETLS: &str = "250-mx.example.org\r\n250 REQUIRETLS\r\n";
+
+        for (tls, ehlo) in [(false, EHLO_REQUIRETLS), (true, EHLO_ASCII)] {
+            let stream = MockStream::new(format!("220 mx.example.org ESMTP\r\n{ehlo}"));
+            let output = stream.output();
+            let mut transport = SmtpTransport::new(SmtpClient::new().assume_tls(tls), stream)
+                .await
+                .unwrap();
+            let email = email("user@example.org", "root@example.org").require_tls(true);
+            assert!(matches!(
+                transport.send(email).await,
+                Err(Error::RequireTlsUnavailable)
+            ));
+            assert!(!String::from_utf8_lossy(&output.lock().unwrap()).contains("MAIL FROM"));
+        }
+
+        let stream = MockStream::new(format!(
+            "220 mx.example.org ESMTP\r\n{EHLO_REQUIRETLS}250 OK\r\n250 OK\r\n354 Go ahead\r\n250 OK\r\n"
+        ));
+        let output = stream.output();
+        let mut transport = SmtpTransport::new(SmtpClient::new().assume_tls(true), stream)
+            .await
+            .unwrap();
+        assert!(transport.server_info().supports_feature(Extension::RequireTls));
+        transport
+            .send(email("user@example.org", "root@example.org").require_tls(true))
+            .await
+            .unwrap();
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("MAIL FROM:<user@example.org> REQUIRETLS\r\n"));
+    }}
+
+    async_test! { test_send_priority_and_deadline, {
+        const EHLO: &str = "250-mx.example.org\r\n250-MT-PRIORITY MIXER\r\n250 DELIVERBY 120\r\n";
+
+        let (result, output) = send(
+            EHLO,
+            email("user@example.org", "root@example.org")
+                .mt_priority(3)
+                .deliver_by(300, DeliverByMode::Return, true),
+        )
+        .await;
+        assert!(result.is_ok());
+        assert!(output.contains("MAIL FROM:<user@example.org> MT-PRIORITY=3 BY=300;RT\r\n"));
+
+        assert_rejected!(
+            EHLO_ASCII,
+            email("user@example.org", "root@example.org").mt_priority(3),
+            Error::UnsupportedParameter("MT-PRIORITY")
+        );
+        assert_rejected!(
+            EHLO,
+            email("user@example.org", "root@example.org").mt_priority(10),
+            Error::InvalidParameter("MT-PRIORITY")
+        );
+        assert_rejected!(
+            EHLO_ASCII,
+            email("user@example.org", "root@example.org").deliver_by(
+                300,
+                DeliverByMode::Notify,
+                false
+            ),
+            Error::UnsupportedParameter("DELIVERBY")
+        );
+        assert_rejected!(
+            EHLO,
+            email("user@example.org", "root@example.org").deliver_by(
+                60,
+                DeliverByMode::Return,
+                false
+            ),
+            Error::InvalidParameter("BY")
+        );
+        assert_rejected!(
+            EHLO,
+            email("user@example.org", "root@example.org").deliver_by(
+                -1_000_000_000,
+                DeliverByMode::Notify,
+                false
+            ),
+            Error::InvalidParameter("BY")
+        );
+
+        // Notifications are allowed below the minimum time.
+        let (result, output) = send(
+            EHLO,
+            email("user@example.org", "root@example.org").deliver_by(
+                -60,
+                DeliverByMode::Notify,
+                false,
+            ),
+        )
+        .await;
+        assert!(result.is_ok());
+        assert!(output.contains("MAIL FROM:<user@example.org> BY=-60;N\r\n"));
+    }}
+
+    async_test! { test_send_future_release, {
+        const EHLO: &str = "250-mx.example.org\r\n250 FUTURERELEASE 86400 2099-01-01T00:00:00Z\r\n";
+        let now = SystemTime::now();
+
+        let (result, output) =
+            send(EHLO, email("user@example.org", "root@example.org").hold_for(3600)).await;
+        assert!(result.is_ok());
+        assert!(output.contains("MAIL FROM:<user@example.org> HOLDFOR=3600\r\n"));
+
+        let date = now + Duration::from_secs(7200);
+        let (result, output) = send(
+            EHLO,
+            email("user@example.org", "root@example.org")
+                .hold_for(3600)
+                .hold_until(date),
+        )
+        .await;
+        assert!(result.is_ok());
+        assert!(output.contains(&format!(
+            "MAIL FROM:<user@example.org> HOLDUNTIL={}\r\n",
+            humantime::format_rfc3339_seconds(date)
+        )));
+
+        assert_rejected!(
+            EHLO_ASCII,
+            email("user@example.org", "root@example.org").hold_for(3600),
+            Error::UnsupportedParameter("FUTURERELEASE")
+        );
+        assert_rejected!(
+            EHLO,
+            email("user@example.org", "root@example.org").hold_for(86401),
+            Error::InvalidParameter("HOLDFOR")
+        );
+        assert_rejected!(
+            EHLO,
+            email("user@example.org", "root@example.org")
+                .hold_until(now + Duration::from_secs(2 * 86400)),
+            Error::InvalidParameter("HOLDUNTIL")
+        );
+        assert_rejected!(
+            "250-mx.example.org\r\n250 FUTURERELEASE 86400 2000-01-01T00:00:00Z\r\n",
+            email("user@example.org", "root@example.org")
+                .hold_until(now + Duration::from_secs(3600)),
+            Error::InvalidParameter("HOLDUNTIL")
+        );
+        // Dates in the past, before the epoch, and after year 9999 are not sent.
+        for date in [
+            now - Duration::from_secs(60),
+            UNIX_EPOCH - Duration::from_secs(1),
+            UNIX_EPOCH + MAX_HOLD_UNTIL + Duration::from_secs(1),
+        ] {
+            assert_rejected!(
+                "250-mx.example.org\r\n250 FUTURERELEASE 999999999999\r\n",
+                email("user@example.org", "root@example.org").hold_until(date),
+                Error::InvalidParameter("HOLDUNTIL")
+            );
+        }
+    }}
+
+    async_test! { test_xclient, {
+        let stream = MockStream::new(
+            "220 mx.example.org ESMTP\r\n250-mx.example.org\r\n250-XCLIENT ADDR NAME HELO\r\n\
+             250 XFORWARD ADDR PROTO\r\n220 mx.example.org ESMTP again\r\n\
+             250-mx.example.org\r\n250 XFORWARD ADDR\r\n250 OK\r\n",
+        );
+        let output = stream.output();
+        let client = SmtpClient::new().hello_name(ClientId::Domain("proxy.example.org".into()));
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+
+        assert!(matches!(
+            transport
+                .xclient(vec![ClientAttribute::Login("user".into())])
+                .await,
+            Err(Error::UnsupportedParameter("LOGIN"))
+        ));
+        assert!(matches!(
+            transport.xclient(vec![]).await,
+            Err(Error::Client("no attributes"))
+        ));
+        let greeting = transport
+            .xclient(vec![
+                ClientAttribute::Addr("192.0.2.1".parse().unwrap()),
+                ClientAttribute::Name("client.example.org".into()),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(greeting.first_line(), Some("mx.example.org ESMTP again"));
+        assert_eq!(transport.greeting(), Some(&greeting));
+        assert!(!transport.supports_feature(Extension::Xclient));
+        assert_eq!(transport.server_info().xforward_attributes(), ["ADDR"]);
+        assert!(matches!(
+            transport.xclient(vec![]).await,
+            Err(Error::UnsupportedCommand("XCLIENT"))
+        ));
+
+        assert!(matches!(
+            transport.xforward(vec![]).await,
+            Err(Error::Client("no attributes"))
+        ));
+        transport
+            .xforward(vec![ClientAttribute::Addr("2001:db8::1".parse().unwrap())])
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "EHLO proxy.example.org\r\nXCLIENT ADDR=192.0.2.1 NAME=client.example.org\r\n\
+             EHLO proxy.example.org\r\nXFORWARD ADDR=IPV6:2001:db8::1\r\n"
+        );
+    }}
+
+    async_test! { test_etrn, {
+        let stream = MockStream::new(
+            "250-mx.example.org\r\n250 ETRN\r\n250 OK, queuing for node example.org started\r\n\
+             251 OK, no messages waiting for node example.org\r\n\
+             252 OK, pending messages for node example.org started\r\n\
+             253 OK, 14 pending messages for node example.org started\r\n\
+             253 OK, pending messages started\r\n\
+             458 Unable to queue messages for node example.org\r\n\
+             459 Node #backup not allowed: unknown queue\r\n\
+             501 Syntax error\r\n",
+        );
+        let output = stream.output();
+        let client = SmtpClient::new().without_greeting();
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+
+        let domain = EtrnNode::Domain("example.org".into());
+        for outcome in [
+            EtrnOutcome::Started,
+            EtrnOutcome::NoMessages,
+            EtrnOutcome::PendingStarted,
+            EtrnOutcome::PendingCount(Some(14)),
+            EtrnOutcome::PendingCount(None),
+            EtrnOutcome::Unavailable,
+        ] {
+            assert_eq!(transport.etrn(domain.clone()).await.unwrap(), outcome);
+        }
+        assert_eq!(
+            transport.etrn(EtrnNode::Queue("backup".into())).await.unwrap(),
+            EtrnOutcome::NotAllowed
+        );
+        assert!(matches!(
+            transport.etrn(EtrnNode::Subdomains("example.org".into())).await,
+            Err(Error::Permanent(_))
+        ));
+        assert!(matches!(
+            transport.atrn(vec![]).await,
+            Err(Error::UnsupportedCommand("ATRN"))
+        ));
+        for node in [
+            EtrnNode::Domain("example.org\r\nQUIT".into()),
+            EtrnNode::Domain("".into()),
+            EtrnNode::Subdomains("example.org example.net".into()),
+            EtrnNode::Queue("a,b".into()),
+        ] {
+            assert!(matches!(
+                transport.etrn(node).await,
+                Err(Error::InvalidParameter("ETRN"))
+            ));
+        }
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with(
+            "ETRN example.org\r\nETRN #backup\r\nETRN @example.org\r\n"
+        ));
+    }}
+
+    async_test! { test_atrn, {
+        let stream = MockStream::new(
+            "250-mx.example.org\r\n250 ATRN\r\n453 You have no mail\r\n\
+             250 OK now reversing the connection\r\n",
+        );
+        let output = stream.output();
+        let client = SmtpClient::new().without_greeting();
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+
+        assert_eq!(transport.atrn(vec![]).await.unwrap(), AtrnOutcome::NoMail);
+        for domains in [
+            vec!["example.org,example.net".into()],
+            vec!["example.org".into(), "".into()],
+            vec!["example.org\r\nQUIT".into()],
+            vec!["example .org".into()],
+        ] {
+            assert!(matches!(
+                transport.atrn(domains).await,
+                Err(Error::InvalidParameter("ATRN"))
+            ));
+        }
+        assert_eq!(
+            transport
+                .atrn(vec!["example.org".into(), "example.net".into()])
+                .await
+                .unwrap(),
+            AtrnOutcome::Reversed
+        );
+        assert!(matches!(
+            transport.etrn(EtrnNode::Domain("example.org".into())).await,
+            Err(Error::UnsupportedCommand("ETRN"))
+        ));
+        transport.into_inner();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("ATRN\r\nATRN example.org,example.net\r\n"));
+    }}
+
+    async_test! { test_vrfy_expn_help_noop, {
+        let stream = MockStream::new(
+            "250 mx.example.org\r\n250 2.1.5 Fred Smith <Smith@USC-ISIF.ARPA>\r\n250 OK\r\n\
+             251 User not local; will forward to <Jones@XYZ.COM>\r\n\
+             252 2.0.0 Cannot VRFY user, but will accept message\r\n\
+             550 5.1.1 No such user\r\n\
+             250-Jon Postel <Postel@USC-ISIF.ARPA>\r\n250-<postmaster>\r\n\
+             250 2.1.5 Sam Q. Smith <SQSmith@USC-ISIF.ARPA>\r\n\
+             250 Not a list\r\n\
+             214-Commands:\r\n214 HELO EHLO MAIL RCPT DATA\r\n250 OK\r\n",
+        );
+        let output = stream.output();
+        let client = SmtpClient::new().without_greeting();
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+
+        let mailbox = |s: &str| s.parse::<Mailbox>().unwrap();
+        assert_eq!(
+            transport.vrfy("Smith".into()).await.unwrap(),
+            VrfyOutcome::Verified(Some(mailbox("Fred Smith <Smith@USC-ISIF.ARPA>")))
+        );
+        assert_eq!(
+            transport.vrfy("Smith".into()).await.unwrap(),
+            VrfyOutcome::Verified(None)
+        );
+        assert_eq!(
+            transport.vrfy("Jones".into()).await.unwrap(),
+            VrfyOutcome::Forwarded(Some(mailbox("<Jones@XYZ.COM>")))
+        );
+        assert_eq!(
+            transport.vrfy("Brown".into()).await.unwrap(),
+            VrfyOutcome::CannotVerify
+        );
+        assert!(matches!(
+            transport.vrfy("Green".into()).await,
+            Err(Error::Permanent(_))
+        ));
+
+        assert_eq!(
+            transport.expn("Example-People".into()).await.unwrap(),
+            [
+                mailbox("Jon Postel <Postel@USC-ISIF.ARPA>"),
+                mailbox("<postmaster>"),
+                mailbox("Sam Q. Smith <SQSmith@USC-ISIF.ARPA>"),
+            ]
+        );
+        assert!(matches!(
+            transport.expn("Smith".into()).await,
+            Err(Error::ResponseParsing(_))
+        ));
+
+        assert_eq!(
+            transport.help(None).await.unwrap(),
+            "Commands:\nHELO EHLO MAIL RCPT DATA"
+        );
+        transport.noop().await.unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("EXPN Example-People\r\nEXPN Smith\r\nHELP\r\nNOOP\r\n"));
+    }}
+
+    async_test! { test_raw_command, {
+        let stream = MockStream::new(
+            "250 mx.example.org\r\n220 Go ahead\r\n250 OK\r\n250 OK\r\n\
+             250-mx.example.org\r\n250 X-ANONYMOUSTLS\r\n",
+        );
+        let output = stream.output();
+        let client = SmtpClient::new()
+            .without_greeting()
+            .hello_name(ClientId::Domain("client.example.org".into()));
+        let mut transport = SmtpTransport::new(client, stream).await.unwrap();
+
+        for command in ["RSET\r\nMAIL FROM:<>", "RSET\r", "RSET\n", "RSET\n\r\n"] {
+            assert!(matches!(
+                transport.command(command).await,
+                Err(Error::LineBreakInCommand)
+            ));
+        }
+        let response = transport.command("X-ANONYMOUSTLS").await.unwrap();
+        assert!(response.has_code(220));
+
+        transport.send_command(NoopCommand).await.unwrap();
+        transport.send_command("RSET\r\n").await.unwrap();
+        assert!(transport.read_response().await.unwrap().has_code(250));
+        assert!(transport.read_response().await.unwrap().has_code(250));
+
+        assert!(!transport.server_info().has_capability("X-ANONYMOUSTLS"));
+        transport.ehlo().await.unwrap();
+        assert!(transport.server_info().has_capability("X-ANONYMOUSTLS"));
+        assert_eq!(
+            transport.ehlo_response().message,
+            ["mx.example.org", "X-ANONYMOUSTLS"]
+        );
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            "EHLO client.example.org\r\nX-ANONYMOUSTLS\r\nNOOP\r\nRSET\r\n\
+             EHLO client.example.org\r\n"
+        );
+    }}
 }
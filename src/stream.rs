@@ -1,33 +1,94 @@
 use std::fmt::{Debug, Display};
 use std::string::String;
 
-use log::debug;
+use log::{debug, warn};
 
 use crate::codec::ClientCodec;
 use crate::commands::*;
 use crate::error::{Error, SmtpResult};
 use crate::extension::ClientId;
-use crate::response::parse_response;
+use crate::response::{parse_response, parse_response_lenient, ResponseWarning};
 
 #[cfg(feature = "runtime-async-std")]
-use async_std::io::{prelude::*, BufReader, Read, ReadExt, Write, WriteExt};
+use async_std::io::{prelude::*, Read, ReadExt, Write, WriteExt};
 #[cfg(feature = "runtime-tokio")]
 use tokio::io::{
     AsyncBufRead as BufRead, AsyncBufReadExt, AsyncRead as Read, AsyncReadExt, AsyncWrite as Write,
-    AsyncWriteExt, BufReader,
+    AsyncWriteExt,
 };
 
+/// Size of the chunks in which the message is read while sending it.
+const MESSAGE_CHUNK_SIZE: usize = 8192;
+
+/// Default maximum length of a response line, including the line ending.
+///
+/// RFC 5321 limits reply lines to 512 octets, this leaves room for misbehaving servers.
+pub(crate) const MAX_RESPONSE_LINE_LENGTH: usize = 4096;
+
+/// Default maximum size of a response.
+pub(crate) const MAX_RESPONSE_SIZE: usize = 65536;
+
+/// Number of lenient parsing warnings kept, the oldest ones are dropped first.
+const MAX_RESPONSE_WARNINGS: usize = 64;
+
 /// SMTP stream.
 #[derive(Debug)]
 pub struct SmtpStream<S: BufRead + Write + Unpin> {
     /// Inner stream.
     inner: S,
+    /// Accept responses deviating from RFC 5321.
+    lenient: bool,
+    /// Last deviations accepted in lenient mode.
+    warnings: Vec<ResponseWarning>,
+    /// Maximum length of a response line, including the line ending.
+    max_line_length: usize,
+    /// Maximum size of a response.
+    max_response_size: usize,
+    /// Replace invalid UTF-8 in responses instead of failing.
+    lossy: bool,
 }
 
 impl<S: BufRead + Write + Unpin> SmtpStream<S> {
     /// Creates new SMTP stream.
     pub fn new(stream: S) -> Self {
-        Self { inner: stream }
+        Self {
+            inner: stream,
+            lenient: false,
+            warnings: vec![],
+            max_line_length: MAX_RESPONSE_LINE_LENGTH,
+            max_response_size: MAX_RESPONSE_SIZE,
+            lossy: false,
+        }
+    }
+
+    /// Sets the limits on the responses read from the server.
+    pub(crate) fn response_limits(self, max_line_length: usize, max_response_size: usize) -> Self {
+        Self {
+            max_line_length,
+            max_response_size,
+            ..self
+        }
+    }
+
+    /// Replaces invalid UTF-8 in responses instead of failing.
+    pub(crate) fn lossy(self, enabled: bool) -> Self {
+        Self {
+            lossy: enabled,
+            ..self
+        }
+    }
+
+    /// Enables lenient response parsing.
+    pub(crate) fn lenient(self, enabled: bool) -> Self {
+        Self {
+            lenient: enabled,
+            ..self
+        }
+    }
+
+    /// Returns the last deviations accepted in lenient mode.
+    pub(crate) fn warnings(&self) -> &[ResponseWarning] {
+        &self.warnings
     }
 
     /// Returns inner stream.
@@ -42,6 +103,16 @@ impl<S: BufRead + Write + Unpin> SmtpStream<S> {
         Ok(ehlo_response)
     }
 
+    /// Sends HELO command and returns server response.
+    pub async fn helo(&mut self, client_id: ClientId) -> SmtpResult {
+        self.command(HeloCommand::new(client_id)).await
+    }
+
+    /// Sends LHLO command and returns server response.
+    pub async fn lhlo(&mut self, client_id: ClientId) -> SmtpResult {
+        self.command(LhloCommand::new(client_id)).await
+    }
+
     /// Send the given SMTP command to the server.
     pub async fn command(&mut self, command: impl Display) -> SmtpResult {
         self.send_command(command).await?;
@@ -67,16 +138,52 @@ impl<S: BufRead + Write + Unpin> SmtpStream<S> {
     }
 
     /// Read an SMTP response from the wire.
+    ///
+    /// Fails with `Error::ResponseLineTooLong` or `Error::ResponseTooLarge` if the
+    /// response exceeds the limits, after which the stream is not usable anymore.
     pub async fn read_response(&mut self) -> SmtpResult {
-        let reader = &mut self.inner;
         let mut buffer = String::with_capacity(100);
+        let mut line = Vec::with_capacity(100);
 
         loop {
-            let read = reader.read_line(&mut buffer).await?;
+            line.clear();
+            let read = (&mut self.inner)
+                .take(self.max_line_length as u64)
+                .read_until(b'\n', &mut line)
+                .await?;
             if read == 0 {
                 break;
             }
+            if read == self.max_line_length && !line.ends_with(b"\n") {
+                return Err(Error::ResponseLineTooLong(self.max_line_length));
+            }
+            if buffer.len() + read > self.max_response_size {
+                return Err(Error::ResponseTooLarge(self.max_response_size));
+            }
+            if self.lossy {
+                buffer.push_str(&String::from_utf8_lossy(&line));
+            } else {
+                buffer.push_str(
+                    std::str::from_utf8(&line)
+                        .map_err(|_| Error::ResponseParsing("Invalid UTF-8 in response"))?,
+                );
+            }
             debug!("<< {}", escape_crlf(&buffer));
+            if self.lenient {
+                if let Some((response, warnings)) = parse_response_lenient(&buffer)? {
+                    for warning in &warnings {
+                        warn!("lenient response parsing: {}", warning);
+                    }
+                    self.warnings.extend(warnings);
+                    let dropped = self.warnings.len().saturating_sub(MAX_RESPONSE_WARNINGS);
+                    self.warnings.drain(..dropped);
+                    if response.is_positive() {
+                        return Ok(response);
+                    }
+                    return Err(response.into());
+                }
+                continue;
+            }
             match parse_response(&buffer) {
                 Ok((_remaining, response)) => {
                     if response.is_positive() {
@@ -95,28 +202,65 @@ impl<S: BufRead + Write + Unpin> SmtpStream<S> {
             }
         }
 
-        Err(std::io::Error::new(std::io::ErrorKind::Other, "incomplete").into())
+        Err(std::io::Error::other("incomplete").into())
     }
 
-    /// Sends the message content.
-    pub(crate) async fn message<T: Read + Unpin>(&mut self, message: T) -> SmtpResult {
-        let mut codec = ClientCodec::new();
+    /// Sends the message content and returns server response.
+    pub(crate) async fn message<T: Read + Unpin>(
+        &mut self,
+        message: T,
+        codec: ClientCodec,
+    ) -> SmtpResult {
+        self.send_message(message, codec).await?;
+        self.read_response().await
+    }
 
-        let mut message_reader = BufReader::new(message);
+    /// Sends the message content like `message`, and appends it unencoded to `copy`.
+    ///
+    /// Used when the message has to be sent again in another transaction.
+    pub(crate) async fn message_copy<T: Read + Unpin>(
+        &mut self,
+        message: T,
+        codec: ClientCodec,
+        copy: &mut Vec<u8>,
+    ) -> SmtpResult {
+        self.encode_message(message, codec, Some(copy)).await?;
+        self.read_response().await
+    }
 
-        let mut message_bytes = Vec::new();
-        message_reader.read_to_end(&mut message_bytes).await?;
+    /// Sends the message content without waiting for response.
+    ///
+    /// The message is read and encoded in chunks of `MESSAGE_CHUNK_SIZE` bytes,
+    /// so it is never buffered in memory as a whole.
+    pub(crate) async fn send_message<T: Read + Unpin>(
+        &mut self,
+        message: T,
+        codec: ClientCodec,
+    ) -> Result<(), Error> {
+        self.encode_message(message, codec, None).await
+    }
 
-        let res: Result<(), Error> = async {
-            codec.encode(&message_bytes, &mut self.inner).await?;
-            self.inner.write_all(b"\r\n.\r\n").await?;
-            self.inner.flush().await?;
-            Ok(())
-        }
-        .await;
-        res?;
+    async fn encode_message<T: Read + Unpin>(
+        &mut self,
+        mut message: T,
+        mut codec: ClientCodec,
+        mut copy: Option<&mut Vec<u8>>,
+    ) -> Result<(), Error> {
+        let mut chunk = vec![0; MESSAGE_CHUNK_SIZE];
 
-        self.read_response().await
+        loop {
+            let read = message.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            codec.encode(&chunk[..read], &mut self.inner).await?;
+            if let Some(copy) = copy.as_mut() {
+                copy.extend_from_slice(&chunk[..read]);
+            }
+        }
+        codec.encode(&[], &mut self.inner).await?;
+        self.inner.flush().await?;
+        Ok(())
     }
 }
 
@@ -128,7 +272,9 @@ fn escape_crlf(string: &str) -> String {
 
 #[cfg(test)]
 mod test {
-    use super::escape_crlf;
+    use super::*;
+    use crate::async_test;
+    use crate::mock::{MockStream, RepeatReader};
 
     #[test]
     fn test_escape_crlf() {
@@ -139,4 +285,67 @@ mod test {
             "EHLO my_name<CRLF>SIZE 42<CRLF>"
         );
     }
+
+    async_test! { test_message_streaming, {
+        const LINE: &[u8] = b"line\r\n.dot\r\n";
+        const COUNT: usize = 1 << 20;
+
+        let mut stream = SmtpStream::new(MockStream::sink("250 OK\r\n"));
+        let response = stream
+            .message(RepeatReader::new(LINE, COUNT), ClientCodec::new())
+            .await
+            .unwrap();
+        assert!(response.has_code(250));
+
+        let sink = stream.into_inner();
+        // One stuffed dot per line, plus the final dot.
+        assert_eq!(sink.written(), LINE.len() * COUNT + COUNT + b".\r\n".len());
+        assert!(sink.max_write() <= MESSAGE_CHUNK_SIZE);
+    }}
+
+    async_test! { test_read_response_limits, {
+        let mut stream = SmtpStream::new(MockStream::new("250 OK\r\n")).response_limits(8, 8);
+        assert!(stream.read_response().await.unwrap().has_code(250));
+
+        let mut stream = SmtpStream::new(MockStream::new("250 Hello\r\n")).response_limits(8, 64);
+        assert!(matches!(
+            stream.read_response().await,
+            Err(Error::ResponseLineTooLong(8))
+        ));
+
+        let mut stream =
+            SmtpStream::new(MockStream::new("250-a\r\n250-b\r\n250 c\r\n")).response_limits(8, 16);
+        assert!(matches!(
+            stream.read_response().await,
+            Err(Error::ResponseTooLarge(16))
+        ));
+    }}
+
+    async_test! { test_read_response_warnings, {
+        let mut stream = SmtpStream::new(MockStream::new("250\r\n".repeat(100))).lenient(true);
+        for _ in 0..100 {
+            assert!(stream.read_response().await.unwrap().has_code(250));
+        }
+        assert_eq!(
+            stream.warnings(),
+            [ResponseWarning::MissingSeparator; MAX_RESPONSE_WARNINGS]
+        );
+    }}
+
+    async_test! { test_read_response_invalid_utf8, {
+        const GREETING: &[u8] = b"220 mx.example.org ESMTP caf\xe9\r\n";
+
+        let mut stream = SmtpStream::new(MockStream::new(GREETING));
+        assert!(matches!(
+            stream.read_response().await,
+            Err(Error::ResponseParsing(_))
+        ));
+
+        let mut stream = SmtpStream::new(MockStream::new(GREETING)).lossy(true);
+        let response = stream.read_response().await.unwrap();
+        assert_eq!(
+            response.first_line(),
+            Some("mx.example.org ESMTP caf\u{fffd}")
+        );
+    }}
 }
@@ -3,12 +3,16 @@ use std::fmt::{self, Display, Formatter};
 use std::pin::Pin;
 use std::str::FromStr;
 use std::task::{Context, Poll};
+use std::time::SystemTime;
 
 use anyhow::{bail, Error, Result};
 #[cfg(feature = "runtime-async-std")]
 use async_std::io::{Cursor, Read};
 use futures::io;
 use pin_project::pin_project;
+
+use crate::error::Error as SmtpError;
+use crate::extension::{DeliverByMode, MailParameter};
 #[cfg(feature = "runtime-tokio")]
 use std::io::Cursor;
 #[cfg(feature = "runtime-tokio")]
@@ -20,17 +24,63 @@ pub struct EmailAddress(String);
 
 impl EmailAddress {
     /// Creates new email address, checking that it does not contain invalid characters.
+    ///
+    /// UTF-8 local parts and domains are accepted,
+    /// see [RFC 6531](https://tools.ietf.org/html/rfc6531).
     pub fn new(address: String) -> Result<EmailAddress> {
         // Do basic checks to avoid injection of control characters into SMTP protocol.  Actual
         // email validation should be done by the server.
-        if address.chars().any(|c| {
-            !c.is_ascii() || c.is_ascii_control() || c.is_ascii_whitespace() || c == '<' || c == '>'
-        }) {
+        if address
+            .chars()
+            .any(|c| c.is_control() || c.is_whitespace() || c == '<' || c == '>')
+        {
             bail!("invalid email address");
         }
 
         Ok(EmailAddress(address))
     }
+
+    /// Returns the local part of the address, before the last `@`.
+    ///
+    /// For addresses without a domain, such as `postmaster`, this is the whole address.
+    pub fn local_part(&self) -> &str {
+        match self.0.rfind('@') {
+            Some(idx) => &self.0[..idx],
+            None => &self.0,
+        }
+    }
+
+    /// Returns the domain of the address, after the last `@`.
+    pub fn domain(&self) -> Option<&str> {
+        self.0.rfind('@').map(|idx| &self.0[idx + 1..])
+    }
+
+    /// Checks if the address only contains ASCII characters.
+    pub fn is_ascii(&self) -> bool {
+        self.0.is_ascii()
+    }
+
+    /// Converts the address into a form that can be sent without SMTPUTF8.
+    ///
+    /// The domain is converted to A-labels (punycode). A UTF-8 local part can not be
+    /// converted, so `Error::SmtpUtf8Required` is returned in this case.
+    pub fn to_ascii(&self) -> Result<EmailAddress, SmtpError> {
+        if self.is_ascii() {
+            return Ok(self.clone());
+        }
+        let local_part = self.local_part();
+        if !local_part.is_ascii() {
+            return Err(SmtpError::SmtpUtf8Required);
+        }
+        match self.domain() {
+            Some(domain) => {
+                let domain = idna::domain_to_ascii(domain)
+                    .map_err(|_| SmtpError::Client("invalid internationalized domain"))?;
+                Ok(EmailAddress(format!("{local_part}@{domain}")))
+            }
+            None => Ok(self.clone()),
+        }
+    }
 }
 
 impl FromStr for EmailAddress {
@@ -59,6 +109,47 @@ impl AsRef<OsStr> for EmailAddress {
     }
 }
 
+/// Mailbox with an optional display name, as in VRFY and EXPN responses
+///
+/// [RFC 5321, section 3.5.1](https://tools.ietf.org/html/rfc5321#section-3.5.1)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Mailbox {
+    /// Display name, e.g. `Fred Smith`
+    pub name: Option<String>,
+    /// Address of the mailbox
+    pub address: EmailAddress,
+}
+
+impl FromStr for Mailbox {
+    type Err = Error;
+
+    /// Parses `Name <address>`, `<address>` or a bare address containing `@`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, address) = match (s.rfind('<'), s.strip_suffix('>')) {
+            (Some(start), Some(rest)) => (s[..start].trim(), &rest[start + 1..]),
+            _ if s.contains('@') => ("", s),
+            _ => bail!("invalid mailbox"),
+        };
+        if address.is_empty() {
+            bail!("invalid mailbox");
+        }
+        Ok(Mailbox {
+            name: Some(name.to_string()).filter(|name| !name.is_empty()),
+            address: address.parse()?,
+        })
+    }
+}
+
+impl Display for Mailbox {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "{} <{}>", name, self.address),
+            None => write!(f, "<{}>", self.address),
+        }
+    }
+}
+
 /// Simple email envelope representation
 ///
 /// We only accept mailboxes, and do not support source routes (as per RFC).
@@ -93,6 +184,28 @@ impl Envelope {
     pub fn from(&self) -> Option<&EmailAddress> {
         self.reverse_path.as_ref()
     }
+
+    /// Checks if all the envelope addresses only contain ASCII characters.
+    pub fn is_ascii(&self) -> bool {
+        self.reverse_path.iter().all(EmailAddress::is_ascii)
+            && self.forward_path.iter().all(EmailAddress::is_ascii)
+    }
+
+    /// Converts all the envelope addresses with `EmailAddress::to_ascii`.
+    pub fn to_ascii(&self) -> Result<Envelope, SmtpError> {
+        Ok(Envelope {
+            forward_path: self
+                .forward_path
+                .iter()
+                .map(EmailAddress::to_ascii)
+                .collect::<Result<_, _>>()?,
+            reverse_path: self
+                .reverse_path
+                .as_ref()
+                .map(EmailAddress::to_ascii)
+                .transpose()?,
+        })
+    }
 }
 
 /// Message buffer for sending.
@@ -155,6 +268,12 @@ pub struct SendableEmail {
     /// Email envelope.
     envelope: Envelope,
     message: Message,
+    /// Whether the message headers contain UTF-8.
+    utf8_headers: bool,
+    /// Whether the message must only be relayed over TLS.
+    require_tls: bool,
+    /// Additional `MAIL FROM` parameters.
+    mail_parameters: Vec<MailParameter>,
 }
 
 impl SendableEmail {
@@ -164,10 +283,17 @@ impl SendableEmail {
         SendableEmail {
             envelope,
             message: Message::Bytes(Cursor::new(message)),
+            utf8_headers: false,
+            require_tls: false,
+            mail_parameters: vec![],
         }
     }
 
     /// Creates new email out of an envelope and a byte reader.
+    ///
+    /// The content is only checked while it is sent, so an error like
+    /// `Error::BareLineEnding` or `Error::LineTooLong` leaves the transport in the middle
+    /// of `DATA`, and it must be dropped.
     pub fn new_with_reader(
         envelope: Envelope,
         message: Box<dyn Read + Send + Sync>,
@@ -175,14 +301,122 @@ impl SendableEmail {
         SendableEmail {
             envelope,
             message: Message::Reader(message),
+            utf8_headers: false,
+            require_tls: false,
+            mail_parameters: vec![],
+        }
+    }
+
+    /// Declares that the message headers contain UTF-8.
+    ///
+    /// Such messages are only sent with SMTPUTF8, even if the envelope is ASCII.
+    pub fn utf8_headers(self, enabled: bool) -> SendableEmail {
+        Self {
+            utf8_headers: enabled,
+            ..self
+        }
+    }
+
+    /// Checks if the message headers were declared to contain UTF-8.
+    pub fn has_utf8_headers(&self) -> bool {
+        self.utf8_headers
+    }
+
+    /// Requires the message to be relayed over TLS only, with REQUIRETLS.
+    ///
+    /// Such messages are only sent over TLS to servers supporting REQUIRETLS, see
+    /// [RFC 8689](https://tools.ietf.org/html/rfc8689). The transport relies on
+    /// `SmtpClient::assume_tls` to know whether the stream is protected.
+    pub fn require_tls(self, enabled: bool) -> SendableEmail {
+        Self {
+            require_tls: enabled,
+            ..self
         }
     }
 
+    /// Checks if the message requires REQUIRETLS.
+    pub fn requires_tls(&self) -> bool {
+        self.require_tls
+    }
+
+    /// Sets the priority of the message, from -9 to 9, with MT-PRIORITY.
+    ///
+    /// The server must support [RFC 6710](https://tools.ietf.org/html/rfc6710).
+    pub fn mt_priority(self, priority: i8) -> SendableEmail {
+        self.mail_parameter(MailParameter::MtPriority(priority))
+    }
+
+    /// Sets a delivery deadline, in seconds from the start of the transaction, with DELIVERBY.
+    ///
+    /// The deadline has at most 9 digits. The server must support [RFC 2852](https://tools.ietf.org/html/rfc2852).
+    pub fn deliver_by(self, seconds: i64, mode: DeliverByMode, trace: bool) -> SendableEmail {
+        self.mail_parameter(MailParameter::DeliverBy {
+            seconds,
+            mode,
+            trace,
+        })
+    }
+
+    /// Asks the server to hold the message for the given number of seconds, with
+    /// FUTURERELEASE.
+    ///
+    /// The server must support [RFC 4865](https://tools.ietf.org/html/rfc4865). This replaces
+    /// a previous `hold_until`.
+    pub fn hold_for(self, seconds: u64) -> SendableEmail {
+        self.without_hold()
+            .mail_parameter(MailParameter::HoldFor(seconds))
+    }
+
+    /// Asks the server to hold the message until the given date, with FUTURERELEASE.
+    ///
+    /// The date must be in the future, and before year 10000. The server must support [RFC 4865](https://tools.ietf.org/html/rfc4865). This replaces
+    /// a previous `hold_for`.
+    pub fn hold_until(self, date: SystemTime) -> SendableEmail {
+        self.without_hold()
+            .mail_parameter(MailParameter::HoldUntil(date))
+    }
+
+    /// Removes the FUTURERELEASE parameters.
+    fn without_hold(mut self) -> SendableEmail {
+        self.mail_parameters
+            .retain(|p| !matches!(p, MailParameter::HoldFor(_) | MailParameter::HoldUntil(_)));
+        self
+    }
+
+    /// Adds a typed parameter, replacing the one of the same kind.
+    fn mail_parameter(mut self, parameter: MailParameter) -> SendableEmail {
+        self.mail_parameters
+            .retain(|p| std::mem::discriminant(p) != std::mem::discriminant(&parameter));
+        self.mail_parameters.push(parameter);
+        self
+    }
+
+    /// Returns the additional `MAIL FROM` parameters set on the message.
+    pub fn mail_parameters(&self) -> &[MailParameter] {
+        &self.mail_parameters
+    }
+
+    /// Checks if the message needs SMTPUTF8.
+    ///
+    /// This is the case if the message headers were declared to contain UTF-8, or if any
+    /// envelope address is not ASCII.
+    pub fn requires_smtp_utf8(&self) -> bool {
+        self.utf8_headers || !self.envelope.is_ascii()
+    }
+
     /// Returns email envelope.
     pub fn envelope(&self) -> &Envelope {
         &self.envelope
     }
 
+    /// Returns the message content if it is held in memory.
+    pub(crate) fn message_bytes(&self) -> Option<&[u8]> {
+        match &self.message {
+            Message::Bytes(cursor) => Some(cursor.get_ref()),
+            Message::Reader(_) => None,
+        }
+    }
+
     /// Returns email message.
     pub fn message(self) -> Message {
         self.message
@@ -193,6 +427,23 @@ impl SendableEmail {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_mailbox() {
+        let mailbox: Mailbox = "Fred Smith <Smith@USC-ISIF.ARPA>".parse().unwrap();
+        assert_eq!(mailbox.name.as_deref(), Some("Fred Smith"));
+        assert_eq!(mailbox.address.to_string(), "Smith@USC-ISIF.ARPA");
+        assert_eq!(mailbox.to_string(), "Fred Smith <Smith@USC-ISIF.ARPA>");
+
+        for s in ["<postmaster>", "postmaster@example.org"] {
+            let mailbox: Mailbox = s.parse().unwrap();
+            assert_eq!(mailbox.name, None);
+            assert_eq!(mailbox.to_string().parse::<Mailbox>().unwrap(), mailbox);
+        }
+        assert!("OK".parse::<Mailbox>().is_err());
+        assert!("Fred <>".parse::<Mailbox>().is_err());
+        assert!("Fred <fred smith@example.org>".parse::<Mailbox>().is_err());
+    }
+
     #[test]
     fn test_email_address() {
         assert!(EmailAddress::new("foobar@example.org".to_string()).is_ok());
@@ -206,5 +457,38 @@ mod test {
         assert!(EmailAddress::new(">foobar@example.org".to_string()).is_err());
         assert!(EmailAddress::new("foo bar@example.org".to_string()).is_err());
         assert!(EmailAddress::new("foobar@exa\r\nmple.org".to_string()).is_err());
+        assert!(EmailAddress::new("bjørn@exämple.org".to_string()).is_ok());
+        assert!(EmailAddress::new("bjørn\u{85}@example.org".to_string()).is_err());
+        assert!(EmailAddress::new("bjørn\u{3000}@example.org".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_email_address_parts() {
+        let address = EmailAddress::new("foo@bar@example.org".to_string()).unwrap();
+        assert_eq!(address.local_part(), "foo@bar");
+        assert_eq!(address.domain(), Some("example.org"));
+
+        let address = EmailAddress::new("postmaster".to_string()).unwrap();
+        assert_eq!(address.local_part(), "postmaster");
+        assert_eq!(address.domain(), None);
+    }
+
+    #[test]
+    fn test_email_address_to_ascii() {
+        let address = EmailAddress::new("user@example.org".to_string()).unwrap();
+        assert_eq!(address.to_ascii().unwrap(), address);
+
+        let address = EmailAddress::new("user@bücher.example".to_string()).unwrap();
+        assert!(!address.is_ascii());
+        assert_eq!(
+            address.to_ascii().unwrap().to_string(),
+            "user@xn--bcher-kva.example"
+        );
+
+        let address = EmailAddress::new("bjørn@example.org".to_string()).unwrap();
+        assert!(matches!(
+            address.to_ascii(),
+            Err(SmtpError::SmtpUtf8Required)
+        ));
     }
 }
@@ -2,6 +2,8 @@
 
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
+use crate::error::Error;
+
 /// Encode a string as xtext
 ///
 /// xtext is defined in <https://www.rfc-editor.org/rfc/rfc3461>
@@ -17,7 +19,7 @@ impl<'a> Display for XText<'a> {
 
             let mut end_iter = end.char_indices();
             let (_, c) = end_iter.next().expect("char");
-            write!(f, "+{:X}", c as u8)?;
+            write!(f, "+{:02X}", c as u8)?;
 
             if let Some((idx, _)) = end_iter.next() {
                 rest = &end[idx..];
@@ -29,9 +31,66 @@ impl<'a> Display for XText<'a> {
     }
 }
 
+/// Encode a string as xtext, escaping every character outside `!`..`~`
+///
+/// Unlike [`XText`], which leaves non-ASCII characters as they are, this is the encoding
+/// required by [RFC 3461](https://www.rfc-editor.org/rfc/rfc3461#section-4): every byte
+/// of a non-ASCII character is hex-escaped.
+#[derive(Debug)]
+pub struct StrictXText<'a>(pub &'a str);
+
+impl<'a> Display for StrictXText<'a> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let mut rest = self.0;
+        while let Some(idx) = rest.find(|c| !('!'..='~').contains(&c) || c == '+' || c == '=') {
+            let (start, end) = rest.split_at(idx);
+            f.write_str(start)?;
+
+            let c = end.chars().next().expect("char");
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                write!(f, "+{byte:02X}")?;
+            }
+            rest = &end[c.len_utf8()..];
+        }
+        f.write_str(rest)
+    }
+}
+
+/// Decodes an xtext string
+///
+/// Fails if a `+` is not followed by two uppercase hexadecimal digits,
+/// or if the result is not valid UTF-8.
+pub fn decode_xtext(s: &str) -> Result<String, Error> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'+' {
+            let hex = [
+                bytes
+                    .next()
+                    .ok_or(Error::InvalidXText("truncated escape"))?,
+                bytes
+                    .next()
+                    .ok_or(Error::InvalidXText("truncated escape"))?,
+            ];
+            if !hex
+                .iter()
+                .all(|c| c.is_ascii_digit() || (b'A'..=b'F').contains(c))
+            {
+                return Err(Error::InvalidXText("invalid escape"));
+            }
+            let hex = std::str::from_utf8(&hex).expect("ASCII");
+            decoded.push(u8::from_str_radix(hex, 16).expect("hexadecimal digits"));
+        } else {
+            decoded.push(byte);
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::XText;
+    use super::{decode_xtext, StrictXText, XText};
 
     #[test]
     fn test() {
@@ -40,10 +99,34 @@ mod tests {
             ("bjørn", "bjørn"),
             ("Ø+= ❤️‰", "Ø+2B+3D+20❤️‰"),
             ("+", "+2B"),
+            ("\t", "+09"),
         ]
         .iter()
         {
             assert_eq!(format!("{}", XText(input)), expect.to_string());
+            assert_eq!(decode_xtext(expect).unwrap(), *input);
+        }
+    }
+
+    #[test]
+    fn test_strict() {
+        for (input, expect) in [
+            ("bjorn", "bjorn"),
+            ("bjørn", "bj+C3+B8rn"),
+            ("a+b=c d", "a+2Bb+3Dc+20d"),
+            ("~!\x7f", "~!+7F"),
+        ]
+        .iter()
+        {
+            assert_eq!(format!("{}", StrictXText(input)), expect.to_string());
+            assert_eq!(decode_xtext(expect).unwrap(), *input);
+        }
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        for input in ["+2", "+", "+ZZ", "+2b", "+FF"] {
+            assert!(decode_xtext(input).is_err(), "{}", input);
         }
     }
 }